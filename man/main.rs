@@ -9,6 +9,7 @@ fn main() {
       lf(&[bold("ultraman"), " ".into(), bold("start"), " ".into(), "[process]".into()]),
       lf(&[bold("ultraman"), " ".into(), bold("run"),   " ".into(), "<command>".into()]),
       lf(&[bold("ultraman"), " ".into(), bold("export")," ".into(), "<format>".into(), " ".into(), "[location]".into()]),
+      lf(&[bold("ultraman"), " ".into(), bold("restart")," ".into(), "<process>".into()]),
     ])
     .section("description", &[
         bold("ultraman"), " ".into(),
@@ -35,6 +36,30 @@ fn main() {
             &["Specify an alternate Procfile to load, implies -d at the Procfile root"]
         )
       ]),
+      p(&[
+        list(
+            &[bold("-c"), ", ".into(), bold("--config"), " ".into(), "[default: ultraman.json]".into()],
+            &["Specify a config file with per-process settings such as readiness probes"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("-d"), ", ".into(), bold("--root")],
+            &["Specify an alternate application root, which processes run in. This defaults to the directory containing the Procfile"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("-w"), ", ".into(), bold("--workspace")],
+            &["Specify a workspace file that lists the roots of several applications to start together"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--app")],
+            &["Specify the root of an application to start together with the others given. Can be given more than once"]
+        )
+      ]),
       p(&[
         list(
             &[bold("-p"), ", ".into(), bold("--port"), " ".into(), "[default: 5000]".into()],
@@ -52,6 +77,96 @@ fn main() {
             &[bold("-n"), ", ".into(), bold("--no-timestamp")],
             &["Include timestamp in output"]
         )
+      ]),
+      p(&[
+        list(
+            &[bold("-u"), ", ".into(), bold("--user")],
+            &["Specify the user to run processes as. Needs root"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--group")],
+            &["Specify the group to run processes as, the primary group of the user if not set. Needs root"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--shell")],
+            &["Specify the shell to run commands with. $SHELL if not set, or /bin/sh if $SHELL is not set either"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--direct-exec")],
+            &["Run commands without shell metacharacters directly, without a shell"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--pty")],
+            &["Give each process a pseudo-terminal, so that it colors its output and does not buffer it"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--tmux")],
+            &["Run each process in its own window of a tmux session, which can be attached to scroll and type into it"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--then")],
+            &["Run a command once every process is running, or ready if it has a readiness probe, then stop them all and exit with its exit code"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--duration")],
+            &["Stop all processes as on SIGINT after the given time, e.g. 90s, 10m or 1h30m. The exit code is 0 unless a process failed before"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--attach")],
+            &["Route stdin to a process, e.g. web.1. Without a process, stdin is routed once one is chosen by typing ~NAME"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--start-delay"), " ".into(), "[default: 0]".into()],
+            &["Specify the amount of time (in milliseconds) to wait after starting a process before starting the next one"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--max-parallel-starts")],
+            &["Specify how many processes may be starting at the same time. A process with a readiness probe is starting until it is ready"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--usage-interval")],
+            &["Specify the interval (in seconds) to print the memory and CPU usage of each process"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--sighup"), " ".into(), "[default: forward]".into()],
+            &["Specify what to do on SIGHUP: forward it to all processes, restart all processes, reload the Procfile and .env, or restart each process type one instance at a time"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--reload")],
+            &["Reload the Procfile and .env when they change"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--init")],
+            &["Run as the init process of a container: reap orphaned processes and forward signals to all processes"]
+        )
       ])
     ])
     .section("run", &[
@@ -71,6 +186,24 @@ fn main() {
             &["Specify an alternate Procfile to load, implies -d at the Procfile root"]
         )
       ]),
+      p(&[
+        list(
+            &[bold("-d"), ", ".into(), bold("--root")],
+            &["Specify an alternate application root, which the command runs in. This defaults to the directory containing the Procfile"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--shell")],
+            &["Specify the shell to run the command with. $SHELL if not set, or /bin/sh if $SHELL is not set either"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--direct-exec")],
+            &["Run a command without shell metacharacters directly, without a shell"]
+        )
+      ]),
     ])
     .section("export", &[
       p(&[bold("ultraman"), " ".into(), "export is used to export your application to another process management format.".into()]),
@@ -143,6 +276,34 @@ fn main() {
         )
       ]),
     ])
+    .section("restart", &[
+      p(&[bold("ultraman"), " ".into(), "restart asks the running ultraman start of the Procfile to restart a process type one instance at a time. The next instance is restarted once the previous one is running again, or ready if it has a readiness probe.".into()]),
+      p(&["The run is found by the same options that were given to start:"]),
+      p(&[
+        list(
+            &[bold("-f"), ", ".into(), bold("--procfile")],
+            &["Specify an alternate Procfile to load, Procfile in the application root if not set"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("-d"), ", ".into(), bold("--root")],
+            &["Specify an alternate application root"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("-w"), ", ".into(), bold("--workspace")],
+            &["Specify the workspace file, and the process type as app/type, e.g. api/web"]
+        )
+      ]),
+      p(&[
+        list(
+            &[bold("--app")],
+            &["Specify the root of an application, once for each that was given to start"]
+        )
+      ]),
+    ])
     .section("export format", &[
       p(&[bold("ultraman"), " ".into(), "currently supports the following output formats:".into()]),
       ul(&[
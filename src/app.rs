@@ -0,0 +1,308 @@
+use crate::config::ProcessConfig;
+use crate::env::{read_env, Env};
+use crate::process;
+
+use serde_derive::Deserialize;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+// Where the files of an application are
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppPaths {
+    pub root: PathBuf,
+    pub procfile: PathBuf,
+    pub env: PathBuf,
+    pub config: PathBuf,
+}
+
+impl AppPaths {
+    // The root is -d, or the directory of the Procfile given with -f, or the current directory.
+    // The files that are not given are looked up in the root.
+    // e.g.) -f services/api/Procfile loads services/api/.env and runs the processes in services/api
+    pub fn resolve(
+        root: Option<PathBuf>,
+        procfile: Option<PathBuf>,
+        env: Option<PathBuf>,
+        config: Option<PathBuf>,
+    ) -> AppPaths {
+        let root = root.unwrap_or_else(|| match procfile.as_ref().and_then(|p| p.parent()) {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        });
+        AppPaths {
+            procfile: procfile.unwrap_or_else(|| root.join("Procfile")),
+            env: env.unwrap_or_else(|| root.join(".env")),
+            config: config.unwrap_or_else(|| root.join("ultraman.json")),
+            root,
+        }
+    }
+
+    // The root, or the cwd of the process in it
+    pub fn working_dir(&self, cwd: Option<&Path>) -> PathBuf {
+        match cwd {
+            Some(cwd) => self.root.join(cwd),
+            None => self.root.clone(),
+        }
+    }
+}
+
+// One application of a workspace, or the only one without a workspace
+#[derive(Clone, Debug)]
+pub struct App {
+    // e.g.) Some("api") for api/web.1
+    pub name: Option<String>,
+    pub paths: AppPaths,
+    // The base port, None for PORT in its .env, $PORT or 5000
+    pub port: Option<String>,
+    pub formation: String,
+    // Process types are numbered across the workspace for their colors, but ports count from the
+    // first type of each app
+    pub first_index: usize,
+}
+
+impl App {
+    // e.g.) api/web for web in the Procfile of api
+    pub fn process_type(&self, name: &str) -> String {
+        match &self.name {
+            Some(app) => format!("{}/{}", app, name),
+            None => name.to_string(),
+        }
+    }
+
+    // e.g.) Some("web") for api/web, None for a process type of another app
+    pub fn procfile_name<'a>(&self, process_type: &'a str) -> Option<&'a str> {
+        match &self.name {
+            Some(app) => process_type
+                .strip_prefix(app.as_str())
+                .and_then(|t| t.strip_prefix('/')),
+            None => Some(process_type),
+        }
+    }
+
+    // PS is web.1 as in the Procfile of the app, not api/web.1
+    pub fn env_for(
+        &self,
+        process_type: &str,
+        concurrency_index: usize,
+        index: usize,
+        config: &ProcessConfig,
+    ) -> Result<Env, Box<dyn std::error::Error>> {
+        process::env_for(
+            self.procfile_name(process_type).unwrap_or(process_type),
+            self.paths.env.clone(),
+            self.port.clone(),
+            concurrency_index,
+            index - self.first_index,
+            config,
+        )
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Workspace {
+    pub apps: Vec<WorkspaceApp>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WorkspaceApp {
+    /// The directory of the app, relative to the workspace file
+    pub root: PathBuf,
+    /// e.g.) api for api/web.1. The name of the root directory if not set
+    pub name: Option<String>,
+    /// The base port. PORT in its .env if set, otherwise --port plus 1000 for each app before it
+    pub port: Option<usize>,
+    /// --formation if not set
+    pub formation: Option<String>,
+}
+
+impl WorkspaceApp {
+    // An app given with --app
+    pub fn at(root: PathBuf) -> Self {
+        WorkspaceApp {
+            root,
+            name: None,
+            port: None,
+            formation: None,
+        }
+    }
+}
+
+pub fn read_workspace(filepath: PathBuf) -> Result<Vec<WorkspaceApp>, Box<dyn std::error::Error>> {
+    let file = File::open(&filepath)?;
+    let workspace: Workspace = serde_json::from_reader(file)?;
+    let dir = filepath.parent().unwrap_or_else(|| Path::new(""));
+    Ok(workspace
+        .apps
+        .into_iter()
+        .map(|app| WorkspaceApp {
+            root: dir.join(&app.root),
+            ..app
+        })
+        .collect())
+}
+
+// The apps of a workspace in order, each with its own name and port base
+pub fn workspace_apps(
+    entries: Vec<WorkspaceApp>,
+    port: Option<&str>,
+    formation: &str,
+) -> Result<Vec<App>, String> {
+    let base = port
+        .map(String::from)
+        .or_else(|| env::var("PORT").ok())
+        .unwrap_or_else(|| String::from("5000"));
+    let base = base
+        .parse::<usize>()
+        .map_err(|_| format!("port {} is not a number", base))?;
+
+    let mut apps: Vec<App> = vec![];
+    for (i, entry) in entries.into_iter().enumerate() {
+        if !entry.root.is_dir() {
+            return Err(format!("app root {} does not exist", entry.root.display()));
+        }
+        // e.g.) api for services/api, also for .
+        let name = match entry.name {
+            Some(name) => name,
+            None => fs::canonicalize(&entry.root)
+                .ok()
+                .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| entry.root.display().to_string()),
+        };
+        if let Some(other) = apps.iter().find(|a| a.name.as_ref() == Some(&name)) {
+            return Err(format!(
+                "apps {} and {} are both named {}, name them in a workspace file",
+                other.paths.root.display(),
+                entry.root.display(),
+                name
+            ));
+        }
+
+        let paths = AppPaths::resolve(Some(entry.root), None, None, None);
+        let has_env_port = read_env(paths.env.clone())
+            .ok()
+            .is_some_and(|env| env.contains_key("PORT"));
+        let port = match entry.port {
+            Some(p) => Some(p.to_string()),
+            None if has_env_port => None,
+            None => Some((base + i * 1000).to_string()),
+        };
+        apps.push(App {
+            name: Some(name),
+            paths,
+            port,
+            formation: entry.formation.unwrap_or_else(|| formation.to_string()),
+            first_index: 0,
+        });
+    }
+    Ok(apps)
+}
+
+// Fail before anything has started, as the process could not be spawned there
+pub fn check_working_dir(name: &str, config: &ProcessConfig) -> Result<(), String> {
+    match &config.cwd {
+        Some(cwd) if !cwd.is_dir() => Err(format!(
+            "working directory {} of {} does not exist",
+            cwd.display(),
+            name
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_resolve() -> anyhow::Result<()> {
+        let paths = AppPaths::resolve(
+            None,
+            Some(PathBuf::from("services/api/Procfile")),
+            None,
+            None,
+        );
+        assert_eq!(paths.root, PathBuf::from("services/api"));
+        assert_eq!(paths.procfile, PathBuf::from("services/api/Procfile"));
+        assert_eq!(paths.env, PathBuf::from("services/api/.env"));
+        assert_eq!(paths.config, PathBuf::from("services/api/ultraman.json"));
+
+        let paths = AppPaths::resolve(None, None, None, None);
+        assert_eq!(paths.root, PathBuf::from("."));
+        assert_eq!(paths.procfile, PathBuf::from("./Procfile"));
+
+        let paths = AppPaths::resolve(
+            Some(PathBuf::from("/srv/app")),
+            Some(PathBuf::from("Procfile.dev")),
+            Some(PathBuf::from("dev.env")),
+            None,
+        );
+        assert_eq!(paths.root, PathBuf::from("/srv/app"));
+        assert_eq!(paths.procfile, PathBuf::from("Procfile.dev"));
+        assert_eq!(paths.env, PathBuf::from("dev.env"));
+        assert_eq!(paths.config, PathBuf::from("/srv/app/ultraman.json"));
+
+        assert_eq!(paths.working_dir(None), PathBuf::from("/srv/app"));
+        assert_eq!(
+            paths.working_dir(Some(Path::new("frontend"))),
+            PathBuf::from("/srv/app/frontend")
+        );
+        assert_eq!(
+            paths.working_dir(Some(Path::new("/tmp"))),
+            PathBuf::from("/tmp")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_workspace_apps() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["api", "web", "worker"].iter() {
+            fs::create_dir_all(dir.path().join("services").join(name))?;
+        }
+        fs::write(dir.path().join("services/web/.env"), "PORT=8000\n")?;
+        let workspace_path = dir.path().join("ultraman-workspace.json");
+        fs::write(
+            &workspace_path,
+            r#"
+{
+  "apps": [
+    { "root": "services/api", "formation": "web=2" },
+    { "root": "services/web" },
+    { "root": "services/worker", "name": "jobs", "port": 9000 }
+  ]
+}
+            "#,
+        )?;
+
+        let entries = read_workspace(workspace_path).expect("failed read workspace");
+        assert_eq!(entries[0].root, dir.path().join("services/api"));
+        let apps = workspace_apps(entries, Some("6000"), "all=1").unwrap();
+        let names = apps
+            .iter()
+            .map(|a| a.name.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["api", "web", "jobs"]);
+        assert_eq!(apps[0].port, Some(String::from("6000")));
+        assert_eq!(apps[0].formation, "web=2");
+        assert_eq!(apps[0].process_type("web"), "api/web");
+        assert_eq!(apps[0].procfile_name("api/web"), Some("web"));
+        assert_eq!(apps[0].procfile_name("jobs/web"), None);
+        // PORT in its .env
+        assert_eq!(apps[1].port, None);
+        assert_eq!(apps[1].formation, "all=1");
+        assert_eq!(apps[2].port, Some(String::from("9000")));
+
+        let entries = vec![
+            WorkspaceApp::at(dir.path().join("services/api")),
+            WorkspaceApp::at(dir.path().join("services/api/")),
+        ];
+        assert!(workspace_apps(entries, None, "all=1")
+            .unwrap_err()
+            .ends_with("are both named api, name them in a workspace file"));
+
+        Ok(())
+    }
+}
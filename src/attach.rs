@@ -0,0 +1,196 @@
+use crate::log;
+use crate::opt::DisplayOpts;
+use crate::process::Process;
+
+use std::io::{self, BufRead, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Set with --attach. Every process then gets a stdin that ultraman can write to,
+// and the attached one gets what is typed into ultraman's terminal.
+static ATTACHED: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+
+pub fn init(name: Option<String>) {
+    let _ = ATTACHED.set(Mutex::new(name));
+}
+
+pub fn is_enabled() -> bool {
+    ATTACHED.get().is_some()
+}
+
+// e.g.) Some("web.1")
+pub fn attached() -> Option<String> {
+    ATTACHED.get().and_then(|a| a.lock().unwrap().clone())
+}
+
+fn set_attached(name: Option<String>) {
+    if let Some(attached) = ATTACHED.get() {
+        *attached.lock().unwrap() = name;
+    }
+}
+
+// A line that starts with ~ switches the attached process, like the escapes of ssh
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    // ~web.1
+    Attach(String),
+    // ~.
+    Detach,
+    // ~?
+    List,
+    // Anything else, and ~~ for a line that starts with ~
+    Line(String),
+}
+
+pub fn parse_input(line: &str) -> Input {
+    match line.strip_prefix('~') {
+        Some(".") => Input::Detach,
+        Some("?") => Input::List,
+        Some(rest) if rest.starts_with('~') => Input::Line(rest.to_string()),
+        Some(name) if !name.trim().is_empty() => Input::Attach(name.trim().to_string()),
+        _ => Input::Line(line.to_string()),
+    }
+}
+
+fn find(procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, name: &str) -> Option<Arc<Mutex<Process>>> {
+    procs
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|p| p.lock().unwrap().name == name)
+        .cloned()
+}
+
+// To the pseudo-terminal with --pty, otherwise to the stdin pipe
+fn write_input(proc: &Arc<Mutex<Process>>, input: &[u8]) -> io::Result<()> {
+    let mut proc = proc.lock().unwrap();
+    if let Some(mut master) = proc.pty.as_ref() {
+        return master.write_all(input);
+    }
+    match proc.child.stdin.as_mut() {
+        Some(stdin) => stdin.write_all(input).and_then(|_| stdin.flush()),
+        None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+    }
+}
+
+// ^D in a terminal, or the end of a pipe
+fn close_input(proc: &Arc<Mutex<Process>>) {
+    let mut proc = proc.lock().unwrap();
+    if let Some(mut master) = proc.pty.as_ref() {
+        let _ = master.write_all(&[0x04]);
+        return;
+    }
+    proc.child.stdin.take();
+}
+
+pub fn build_attach_thread(
+    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    opts: DisplayOpts,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("attach stdin"))
+        .spawn(move || {
+            match attached() {
+                Some(name) => log::system(
+                    &opts,
+                    &format!(
+                        "stdin attached to {}, type ~NAME to switch, ~. to detach and ~? to list",
+                        name
+                    ),
+                ),
+                None => log::system(
+                    &opts,
+                    "type ~NAME to attach stdin to a process and ~? to list",
+                ),
+            }
+
+            let stdin = io::stdin();
+            let is_terminal = unsafe { nix::libc::isatty(stdin.as_raw_fd()) } == 1;
+            let mut reader = stdin.lock();
+            loop {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    // ^D in a terminal only ends what was typed so far, so it can be read again
+                    Ok(0) => {
+                        if let Some(proc) = attached().and_then(|name| find(&procs, &name)) {
+                            close_input(&proc);
+                        }
+                        if is_terminal {
+                            // A terminal that has gone away keeps returning 0 too
+                            thread::sleep(Duration::from_millis(100));
+                            continue;
+                        }
+                        return;
+                    }
+                    Ok(_) => (),
+                    Err(_) => return,
+                }
+
+                match parse_input(line.trim_end_matches('\n')) {
+                    Input::Attach(name) => {
+                        if find(&procs, &name).is_some() {
+                            log::system(&opts, &format!("stdin attached to {}", name));
+                            set_attached(Some(name));
+                        } else {
+                            log::system(&opts, &format!("no process {} to attach stdin to", name));
+                        }
+                    }
+                    Input::Detach => {
+                        log::system(&opts, "stdin detached");
+                        set_attached(None);
+                    }
+                    Input::List => {
+                        let names = procs
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .map(|p| p.lock().unwrap().name.clone())
+                            .collect::<Vec<_>>();
+                        log::system(
+                            &opts,
+                            &format!(
+                                "stdin attached to {}, running: {}",
+                                attached().unwrap_or_else(|| String::from("nothing")),
+                                names.join(", ")
+                            ),
+                        );
+                    }
+                    Input::Line(content) => {
+                        let proc = match attached().and_then(|name| find(&procs, &name)) {
+                            Some(p) => p,
+                            None => continue,
+                        };
+                        if write_input(&proc, format!("{}\n", content).as_bytes()).is_err() {
+                            let name = proc.lock().unwrap().name.clone();
+                            log::system(&opts, &format!("{} does not read stdin anymore", name));
+                        }
+                    }
+                }
+            }
+        })
+        .expect("failed attach stdin")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_parse_input() -> anyhow::Result<()> {
+        assert_eq!(parse_input("~web.1"), Input::Attach(String::from("web.1")));
+        assert_eq!(parse_input("~."), Input::Detach);
+        assert_eq!(parse_input("~?"), Input::List);
+        assert_eq!(parse_input("~~home"), Input::Line(String::from("~home")));
+        assert_eq!(parse_input("~"), Input::Line(String::from("~")));
+        assert_eq!(
+            parse_input("puts 1 + 1"),
+            Input::Line(String::from("puts 1 + 1"))
+        );
+        assert_eq!(parse_input(""), Input::Line(String::new()));
+
+        Ok(())
+    }
+}
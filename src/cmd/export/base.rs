@@ -1,8 +1,11 @@
+use crate::app::AppPaths;
 use crate::cmd::export::ExportOpts;
+use crate::config::{read_config, Config};
+use crate::credentials;
 use crate::env::read_env;
 
 use handlebars::Handlebars;
-use nix::unistd::{chown, User};
+use nix::unistd::chown;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
 use std::env;
@@ -33,8 +36,8 @@ pub trait Exportable {
         let display = location.clone().into_os_string().into_string().unwrap();
         create_dir_all(&location).expect(&format!("Could not create: {}", display));
 
-        // self.chown(&username, &self.log_path());
-        // self.chown(&username, &self.run_path());
+        // self.chown(&username, &self.log_path())?;
+        // self.chown(&username, &self.run_path())?;
         Ok(())
     }
 
@@ -70,13 +73,12 @@ pub trait Exportable {
             .unwrap_or_else(|| env::current_dir().unwrap())
     }
 
-    fn chown(&self, username: &str, dir: &PathBuf) {
+    fn chown(&self, username: &str, dir: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
         let display = dir.clone().into_os_string().into_string().unwrap();
-        let user = User::from_name(username)
-            .expect(&format!("Could not get user from {}", username))
-            .expect(&format!("Could not exists user: {}", username));
+        let user = credentials::find_user(username)?;
         chown(dir.as_path(), Some(user.uid), None)
-            .expect(&format!("Could not chown {} to {}", display, username))
+            .map_err(|e| format!("Could not chown {} to {}: {}", display, username, e))?;
+        Ok(())
     }
 
     fn clean(&self, filepath: &PathBuf) {
@@ -135,6 +137,18 @@ pub trait Exportable {
         env_without_port
     }
 
+    // Resolved against the application root like start, read once per export
+    fn config(&self) -> Result<Config, Box<dyn std::error::Error>> {
+        let opts = self.ref_opts();
+        let paths = AppPaths::resolve(
+            opts.root_path.clone(),
+            Some(opts.procfile_path.clone()),
+            Some(opts.env_path.clone()),
+            opts.config_path.clone(),
+        );
+        read_config(paths.config)
+    }
+
     fn create_dir_recursive(&self, dir_path: &PathBuf) {
         let display = dir_path.clone().into_os_string().into_string().unwrap();
         create_dir_all(dir_path).expect(&format!("Could not create: {}", display))
@@ -75,6 +75,10 @@ pub struct ExportOpts {
     )]
     pub procfile_path: PathBuf,
 
+    /// Specify a config file with per-process settings such as readiness probes, ultraman.json in the application root if not set
+    #[structopt(name = "CONFIG", short = "c", long = "config", parse(from_os_str))]
+    pub config_path: Option<PathBuf>,
+
     /// Specify an alternate application root. This defaults to the directory containing the Procfile.
     #[structopt(name = "ROOT", short = "d", long = "root", parse(from_os_str))]
     pub root_path: Option<PathBuf>,
@@ -112,7 +116,6 @@ fn new(opts: &ExportOpts) -> Box<dyn Exportable> {
     match export_format(format) {
         ExportFormat::Upstart => {
             let mut expo = upstart::Exporter::boxed_new();
-            // Read the formation from the command line option and always call it before process_len for the convenience of setting concurrency
             procfile.set_concurrency(&opts.formation);
             expo.procfile = procfile;
             expo.opts = opts.clone();
@@ -158,7 +161,7 @@ fn new(opts: &ExportOpts) -> Box<dyn Exportable> {
 
 pub fn run(opts: ExportOpts) -> Result<(), Box<dyn std::error::Error>> {
     let exporter = new(&opts);
-    exporter.export().expect("failed ultraman export");
+    exporter.export()?;
 
     Ok(())
 }
@@ -49,6 +49,7 @@ impl Default for Exporter {
                 user: None,
                 env_path: PathBuf::from(".env"),
                 procfile_path: PathBuf::from("Procfile"),
+                config_path: None,
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: String::from("5"),
             },
@@ -1,6 +1,8 @@
 use super::base::{Exportable, Template};
 use crate::cmd::export::ExportOpts;
+use crate::config::ProcessConfig;
 use crate::env::read_env;
+use crate::limits::Rlimit;
 use crate::process::port_for;
 use crate::procfile::Procfile;
 use handlebars::to_json;
@@ -28,6 +30,7 @@ struct AppConfDataParams {
     environment: String,
     stdout_logfile: String,
     stderr_logfile: String,
+    umask: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -55,6 +58,7 @@ impl Default for Exporter {
                 user: None,
                 env_path: PathBuf::from(".env"),
                 procfile_path: PathBuf::from("Procfile"),
+                config_path: None,
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: String::from("5"),
             },
@@ -117,21 +121,67 @@ impl Exporter {
         let result = re_env.replace_all(command, "%(ENV_$envname)s");
         result.to_string()
     }
+
+    // supervisord has no settings for these, so the command is wrapped,
+    // e.g.) /bin/bash -c 'ulimit -n 65536 && exec nice -n 10 bundle exec rake index'
+    fn wrap_command(&self, command: &str, config: &ProcessConfig) -> String {
+        let mut ulimit = vec![];
+        for (name, limit) in config
+            .rlimits
+            .as_ref()
+            .map(|r| r.entries())
+            .unwrap_or_default()
+        {
+            // ulimit takes the address space and core file size in KiB
+            let (flag, scale) = match name {
+                "nofile" => ("-n", 1),
+                "nproc" => ("-u", 1),
+                "as" => ("-v", 1024),
+                _ => ("-c", 1024),
+            };
+            let limit = match limit {
+                Rlimit::Limited(n) => Rlimit::Limited(n / scale),
+                l => l,
+            };
+            ulimit.push(format!("{} {}", flag, limit.format("unlimited")));
+        }
+        let mut exec = vec![];
+        if let Some(nice) = config.nice {
+            exec.push(format!("nice -n {}", nice));
+        }
+        if let Some(cpus) = &config.cpu_affinity {
+            let cpus = cpus.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            exec.push(format!("taskset -c {}", cpus.join(",")));
+        }
+        if ulimit.is_empty() && exec.is_empty() {
+            return command.to_string();
+        }
+
+        exec.push(command.to_string());
+        let mut script = format!("exec {}", exec.join(" "));
+        if !ulimit.is_empty() {
+            script = format!("ulimit {} && {}", ulimit.join(" "), script);
+        }
+        format!("/bin/bash -c '{}'", script.replace('\'', "'\\''"))
+    }
 }
 
 impl Exportable for Exporter {
     fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.base_export().expect("failed execute base_export");
 
+        let config = self.config()?;
         let mut index = 0;
         let mut service_names = vec![];
         let mut data: Vec<AppConfDataParams> = vec![];
         for (name, pe) in self.procfile.data.iter() {
             index += 1;
             let con = pe.concurrency.get();
+            let config = config.find_by(name);
             for n in 0..con {
                 let program = format!("{}-{}-{}", self.app(), &name, n + 1);
-                let process_command = self.replace_env_for_supervisord(&pe.command);
+                let process_command =
+                    self.wrap_command(&self.replace_env_for_supervisord(&pe.command), &config);
                 let environment = self.environment(index, n);
                 let display_log = self.log_path().into_os_string().into_string().unwrap();
                 let stdout_logfile = format!("{}/{}-{}.log", &display_log, &name, n + 1);
@@ -145,6 +195,7 @@ impl Exportable for Exporter {
                     environment,
                     stdout_logfile,
                     stderr_logfile,
+                    umask: config.umask.map(|u| format!("{:03o}", u)),
                 });
             }
         }
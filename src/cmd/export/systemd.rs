@@ -1,7 +1,9 @@
 use super::base::{EnvParameter, Exportable, Template};
 use crate::cmd::export::ExportOpts;
+use crate::config::ProcessConfig;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
+use crate::readiness::Probe;
 use handlebars::to_json;
 use serde_derive::Serialize;
 use serde_json::value::{Map, Value as Json};
@@ -29,6 +31,13 @@ struct ProcessServiceParams {
     process_command: String,
     env_without_port: Vec<EnvParameter>,
     timeout: String,
+    kill_signal: Option<String>,
+    is_notify: bool,
+    watchdog_sec: Option<u64>,
+    limits: Vec<EnvParameter>,
+    nice: Option<String>,
+    umask: Option<String>,
+    cpu_affinity: Option<String>,
 }
 
 impl Default for Exporter {
@@ -49,6 +58,7 @@ impl Default for Exporter {
                 user: None,
                 env_path: PathBuf::from(".env"),
                 procfile_path: PathBuf::from("Procfile"),
+                config_path: None,
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: String::from("5"),
             },
@@ -94,6 +104,7 @@ impl Exporter {
         process_name: &str,
         index: usize,
         con_index: usize,
+        config: ProcessConfig,
     ) -> Map<String, Json> {
         let mut data = Map::new();
         let ps = ProcessServiceParams {
@@ -109,7 +120,31 @@ impl Exporter {
             process_name: process_name.to_string(),
             process_command: pe.command.to_string(),
             env_without_port: self.env_without_port(),
-            timeout: self.opts.timeout.clone(),
+            timeout: config
+                .stop_timeout
+                .map(|t| t.to_string())
+                .unwrap_or(self.opts.timeout.clone()),
+            kill_signal: config.stop_signal.map(|s| s.as_str().to_string()),
+            is_notify: matches!(config.readiness.map(|r| r.probe), Some(Probe::Notify)),
+            watchdog_sec: config.watchdog.map(|w| w.timeout),
+            limits: config
+                .rlimits
+                .map(|r| r.entries())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, limit)| EnvParameter {
+                    key: name.to_uppercase(),
+                    value: limit.format("infinity"),
+                })
+                .collect(),
+            nice: config.nice.map(|n| n.to_string()),
+            umask: config.umask.map(|u| format!("{:04o}", u)),
+            cpu_affinity: config.cpu_affinity.map(|cpus| {
+                cpus.iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }),
         };
         data.insert("process_service".to_string(), to_json(&ps));
         data
@@ -120,6 +155,7 @@ impl Exportable for Exporter {
     fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.base_export().expect("failed execute base_export");
 
+        let config = self.config()?;
         let mut index = 0;
         let mut service_names = vec![];
         let mut clean_paths: Vec<PathBuf> = vec![];
@@ -132,7 +168,13 @@ impl Exportable for Exporter {
                 let process_name = format!("{}.{}", &name, n);
                 let service_filename = format!("{}-{}.service", &name, &process_name);
                 let output_path = self.output_path(service_filename.clone());
-                let data = self.make_process_service_data(pe, &process_name, index, n);
+                let data = self.make_process_service_data(
+                    pe,
+                    &process_name,
+                    index,
+                    n,
+                    config.find_by(name),
+                );
 
                 clean_paths.push(output_path.clone());
                 tmpl_data.push(Template {
@@ -1,5 +1,6 @@
 use super::base::{EnvParameter, Exportable, Template};
 use crate::cmd::export::ExportOpts;
+use crate::config::ProcessConfig;
 use crate::process::port_for;
 use crate::procfile::{Procfile, ProcfileEntry};
 
@@ -29,6 +30,9 @@ struct ProcessParams {
     setuid: String,
     chdir: String,
     exec: String,
+    limits: Vec<EnvParameter>,
+    nice: Option<String>,
+    umask: Option<String>,
 }
 
 // http://takoyaking.hatenablog.com/entry/anonymous_lifetime
@@ -75,8 +79,21 @@ impl Exporter {
         app_name: &str,
         index: usize,
         con_index: usize,
+        config: ProcessConfig,
     ) -> Map<String, Json> {
         let mut data = Map::new();
+        // Upstart has no stanza for CPU affinity
+        let exec = match &config.cpu_affinity {
+            Some(cpus) => format!(
+                "taskset -c {} {}",
+                cpus.iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                pe.command
+            ),
+            None => pe.command.to_string(),
+        };
 
         let p = ProcessParams {
             app: self.app(),
@@ -90,7 +107,19 @@ impl Exporter {
             env_without_port: self.env_without_port(),
             setuid: self.username(),
             chdir: self.root_path().into_os_string().into_string().unwrap(),
-            exec: pe.command.to_string(),
+            exec,
+            limits: config
+                .rlimits
+                .map(|r| r.entries())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, limit)| EnvParameter {
+                    key: name.to_string(),
+                    value: limit.format("unlimited"),
+                })
+                .collect(),
+            nice: config.nice.map(|n| n.to_string()),
+            umask: config.umask.map(|u| format!("{:03o}", u)),
         };
         data.insert("process".to_string(), to_json(&p));
         data
@@ -115,6 +144,7 @@ impl Default for Exporter {
                 user: None,
                 env_path: PathBuf::from(".env"),
                 procfile_path: PathBuf::from("Procfile"),
+                config_path: None,
                 root_path: Some(env::current_dir().unwrap()),
                 timeout: String::from("5"),
             },
@@ -126,6 +156,7 @@ impl Exportable for Exporter {
     fn export(&self) -> Result<(), Box<dyn std::error::Error>> {
         self.base_export().expect("failed execute base_export");
 
+        let config = self.config()?;
         let mut clean_paths: Vec<PathBuf> = vec![];
         let mut tmpl_data: Vec<Template> = vec![];
 
@@ -160,7 +191,7 @@ impl Exportable for Exporter {
                 clean_paths.push(output_path.clone());
                 tmpl_data.push(Template {
                     template_path: self.process_tmpl_path(),
-                    data: self.make_process_data(pe, &name, index, n),
+                    data: self.make_process_data(pe, &name, index, n, config.find_by(name)),
                     output_path,
                 });
             }
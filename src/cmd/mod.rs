@@ -1,3 +1,4 @@
 pub mod export;
+pub mod restart;
 pub mod run;
 pub mod start;
@@ -0,0 +1,116 @@
+use crate::app::{self, App, AppPaths, WorkspaceApp};
+#[cfg(target_os = "linux")]
+use crate::control;
+use crate::process_group;
+use crate::procfile::read_procfile;
+
+use std::path::PathBuf;
+use structopt::{clap, StructOpt};
+
+#[derive(StructOpt, Debug)]
+#[structopt(setting(clap::AppSettings::ColoredHelp))]
+pub struct RestartOpts {
+    /// Process type to restart one instance at a time, e.g. api/web for an app of a workspace
+    #[structopt(name = "APP_NAME")]
+    pub app_name: String,
+
+    /// Specify an Procfile to load, Procfile in the application root if not set. Implies -d at the Procfile root
+    #[structopt(name = "PROCFILE", short = "f", long = "procfile", parse(from_os_str))]
+    pub procfile_path: Option<PathBuf>,
+
+    /// Specify an alternate application root, as given to start
+    #[structopt(name = "ROOT", short = "d", long = "root", parse(from_os_str))]
+    pub root_path: Option<PathBuf>,
+
+    /// Specify the workspace file given to start
+    #[structopt(
+        name = "WORKSPACE",
+        short = "w",
+        long = "workspace",
+        parse(from_os_str),
+        conflicts_with_all = &["PROCFILE", "ROOT", "APP_ROOT"]
+    )]
+    pub workspace_path: Option<PathBuf>,
+
+    /// Specify the roots of the applications given to start. Can be given more than once
+    #[structopt(
+        name = "APP_ROOT",
+        long = "app",
+        parse(from_os_str),
+        number_of_values = 1,
+        conflicts_with_all = &["PROCFILE", "ROOT"]
+    )]
+    pub app_roots: Vec<PathBuf>,
+}
+
+// Ask the `ultraman start` that runs the Procfile for a rolling restart. Its progress is in the system log
+pub fn run(opts: RestartOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let apps = load_apps(&opts)?;
+    let mut is_known = false;
+    for app in apps.iter() {
+        if let Some(name) = app.procfile_name(&opts.app_name) {
+            is_known |= read_procfile(app.paths.procfile.clone())?
+                .data
+                .contains_key(name);
+        }
+    }
+    if !is_known {
+        return Err(format!("{} is not in the Procfile", opts.app_name).into());
+    }
+    // The same file that start tracks its processes by
+    let tracked_path = opts
+        .workspace_path
+        .clone()
+        .unwrap_or_else(|| apps[0].paths.procfile.clone());
+    let owner = process_group::owner(&tracked_path)
+        .ok_or_else(|| format!("ultraman start is not running {}", tracked_path.display()))?;
+
+    send(owner, &format!("restart {}", opts.app_name))?;
+    println!(
+        "requested rolling restart of {} (pid {})",
+        opts.app_name, owner
+    );
+
+    Ok(())
+}
+
+// Resolved as start does, so that the same options find the same run
+fn load_apps(opts: &RestartOpts) -> Result<Vec<App>, Box<dyn std::error::Error>> {
+    let entries = match &opts.workspace_path {
+        Some(path) => app::read_workspace(path.clone())?,
+        None => opts
+            .app_roots
+            .iter()
+            .cloned()
+            .map(WorkspaceApp::at)
+            .collect(),
+    };
+    if !entries.is_empty() {
+        // Only the names and paths matter here
+        return Ok(app::workspace_apps(entries, Some("5000"), "all=1")?);
+    }
+
+    let paths = AppPaths::resolve(
+        opts.root_path.clone(),
+        opts.procfile_path.clone(),
+        None,
+        None,
+    );
+    Ok(vec![App {
+        name: None,
+        paths,
+        port: None,
+        formation: String::from("all=1"),
+        first_index: 0,
+    }])
+}
+
+#[cfg(target_os = "linux")]
+fn send(pid: i32, request: &str) -> Result<(), Box<dyn std::error::Error>> {
+    control::send(pid, request)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(_pid: i32, _request: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Err("ultraman restart is only supported on Linux".into())
+}
@@ -1,12 +1,13 @@
+use crate::app::AppPaths;
 use crate::env::read_env;
 use crate::procfile::read_procfile;
+use crate::shell;
 
 use nix::sys::wait::WaitStatus;
 use nix::unistd::{fork, pause, ForkResult};
 use nix::{self};
-use std::env::{self as std_env};
 use std::path::PathBuf;
-use std::process::{exit, Command};
+use std::process::exit;
 use std::thread;
 use structopt::{clap, StructOpt};
 
@@ -17,49 +18,45 @@ pub struct RunOpts {
     #[structopt(name = "APP_NAME")]
     pub app_name: String,
 
-    /// Specify an environment file to load
-    #[structopt(
-        name = "ENV",
-        short = "e",
-        long = "env",
-        parse(from_os_str),
-        default_value = ".env"
-    )]
-    pub env_path: PathBuf,
+    /// Specify an environment file to load, .env in the application root if not set
+    #[structopt(name = "ENV", short = "e", long = "env", parse(from_os_str))]
+    pub env_path: Option<PathBuf>,
 
-    /// Specify an Procfile to load
-    #[structopt(
-        name = "PROCFILE",
-        short = "f",
-        long = "procfile",
-        parse(from_os_str),
-        default_value = "Procfile"
-    )]
-    pub procfile_path: PathBuf,
+    /// Specify an Procfile to load, Procfile in the application root if not set. Implies -d at the Procfile root
+    #[structopt(name = "PROCFILE", short = "f", long = "procfile", parse(from_os_str))]
+    pub procfile_path: Option<PathBuf>,
+
+    /// Specify an alternate application root, which the command runs in. This defaults to the directory containing the Procfile.
+    #[structopt(name = "ROOT", short = "d", long = "root", parse(from_os_str))]
+    pub root_path: Option<PathBuf>,
+
+    /// Specify the shell to run the command with. $SHELL if not set, or /bin/sh if $SHELL is not set either
+    #[structopt(name = "SHELL", long = "shell")]
+    pub shell: Option<String>,
+
+    /// Run a command without shell metacharacters directly, without a shell
+    #[structopt(name = "DIRECT_EXEC", long = "direct-exec")]
+    pub is_direct_exec: bool,
 }
 
 pub fn run(opts: RunOpts) {
     let app_name = opts.app_name;
-    let procfile_path = opts.procfile_path;
-    let env_path = opts.env_path;
+    let paths = AppPaths::resolve(opts.root_path, opts.procfile_path, opts.env_path, None);
 
-    let procfile = read_procfile(procfile_path).expect("failed read Procfile");
+    let procfile = read_procfile(paths.procfile).expect("failed read Procfile");
     let pe = procfile.find_by(&app_name);
 
-    let mut read_env = read_env(env_path).expect("failed read .env");
+    let mut read_env = read_env(paths.env).expect("failed read .env");
     read_env.insert(String::from("PORT"), String::from("5000"));
     read_env.insert(String::from("PS"), String::from(&app_name));
 
-    let shell = std_env::var("SHELL").expect("$SHELL is not set");
-
     unsafe {
         match fork() {
             Ok(fork_result) => match fork_result {
                 ForkResult::Child => {
-                    let _ = Command::new(shell)
-                        .arg("-c")
-                        .arg(&pe.command)
+                    let _ = shell::command(&pe.command, opts.shell.as_deref(), opts.is_direct_exec)
                         .envs(read_env)
+                        .current_dir(&paths.root)
                         .spawn()
                         .expect("failed execute command");
                     // we need the child to stay alive until the parent calls read
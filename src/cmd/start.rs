@@ -1,11 +1,38 @@
+use crate::app::{self, App, AppPaths, WorkspaceApp};
+use crate::attach;
+use crate::config::{read_config, Config};
+#[cfg(target_os = "linux")]
+use crate::control;
+use crate::credentials;
+use crate::cron;
+use crate::duration;
+#[cfg(target_os = "linux")]
+use crate::log;
+#[cfg(target_os = "linux")]
+use crate::notify;
 use crate::opt::DisplayOpts;
 use crate::output;
 use crate::process::{self, Process};
+use crate::process_group;
 use crate::procfile::read_procfile;
+use crate::readiness;
+use crate::reload;
+use crate::sandbox;
 use crate::signal;
+use crate::startup;
+use crate::then;
+use crate::tmux;
+use crate::trigger;
+#[cfg(target_os = "linux")]
+use crate::usage;
+#[cfg(target_os = "linux")]
+use crate::watch;
 
+use std::env;
 use std::path::PathBuf;
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use structopt::{clap, StructOpt};
 
 #[derive(StructOpt, Debug)]
@@ -20,25 +47,41 @@ pub struct StartOpts {
     )]
     pub formation: String,
 
-    /// Specify an environment file to load
+    /// Specify an environment file to load, .env in the application root if not set
+    #[structopt(name = "ENV", short = "e", long = "env", parse(from_os_str))]
+    pub env_path: Option<PathBuf>,
+
+    /// Specify an Procfile to load, Procfile in the application root if not set. Implies -d at the Procfile root
+    #[structopt(name = "PROCFILE", short = "f", long = "procfile", parse(from_os_str))]
+    pub procfile_path: Option<PathBuf>,
+
+    /// Specify a config file with per-process settings such as readiness probes, ultraman.json in the application root if not set
+    #[structopt(name = "CONFIG", short = "c", long = "config", parse(from_os_str))]
+    pub config_path: Option<PathBuf>,
+
+    /// Specify an alternate application root, which processes run in. This defaults to the directory containing the Procfile.
+    #[structopt(name = "ROOT", short = "d", long = "root", parse(from_os_str))]
+    pub root_path: Option<PathBuf>,
+
+    /// Specify a workspace file that lists the roots of several applications to start together
     #[structopt(
-        name = "ENV",
-        short = "e",
-        long = "env",
+        name = "WORKSPACE",
+        short = "w",
+        long = "workspace",
         parse(from_os_str),
-        default_value = ".env"
+        conflicts_with_all = &["PROCFILE", "ROOT", "ENV", "CONFIG", "APP_ROOT"]
     )]
-    pub env_path: PathBuf,
+    pub workspace_path: Option<PathBuf>,
 
-    /// Specify an Procfile to load
+    /// Specify the root of an application to start together with the others given. Can be given more than once
     #[structopt(
-        name = "PROCFILE",
-        short = "f",
-        long = "procfile",
+        name = "APP_ROOT",
+        long = "app",
         parse(from_os_str),
-        default_value = "Procfile"
+        number_of_values = 1,
+        conflicts_with_all = &["PROCFILE", "ROOT", "ENV", "CONFIG"]
     )]
-    pub procfile_path: PathBuf,
+    pub app_roots: Vec<PathBuf>,
 
     /// Specify the amount of time (in seconds) processes have to shutdown gracefully before receiving a SIGTERM
     #[structopt(
@@ -53,6 +96,71 @@ pub struct StartOpts {
     #[structopt(name = "PORT", short = "p", long = "port")]
     pub port: Option<String>,
 
+    /// Specify the user to run processes as. Needs root
+    #[structopt(name = "USER", short = "u", long = "user")]
+    pub user: Option<String>,
+
+    /// Specify the group to run processes as, the primary group of the user if not set. Needs root
+    #[structopt(name = "GROUP", long = "group")]
+    pub group: Option<String>,
+
+    /// Specify the shell to run commands with. $SHELL if not set, or /bin/sh if $SHELL is not set either
+    #[structopt(name = "SHELL", long = "shell")]
+    pub shell: Option<String>,
+
+    /// Run commands without shell metacharacters directly, without a shell
+    #[structopt(name = "DIRECT_EXEC", long = "direct-exec")]
+    pub is_direct_exec: bool,
+
+    /// Give each process a pseudo-terminal, so that it colors its output and does not buffer it
+    #[structopt(name = "PTY", long = "pty")]
+    pub is_pty: bool,
+
+    /// Run each process in its own window of a tmux session, which can be attached to scroll and type into it
+    #[structopt(name = "TMUX", long = "tmux", conflicts_with = "ATTACH")]
+    pub is_tmux: bool,
+
+    /// Run a command once every process is running, or ready if it has a readiness probe, then stop them all and exit with its exit code
+    #[structopt(name = "THEN", long = "then", conflicts_with = "ATTACH")]
+    pub then: Option<String>,
+
+    /// Stop all processes as on SIGINT after the given time, e.g. 90s, 10m or 1h30m. The exit code is 0 unless a process failed before
+    #[structopt(name = "DURATION", long = "duration")]
+    pub duration: Option<String>,
+
+    /// Route stdin to a process, e.g. web.1. Without a process, stdin is routed once one is chosen by typing ~NAME
+    #[structopt(name = "ATTACH", long = "attach")]
+    pub attach: Option<Option<String>>,
+
+    /// Specify the amount of time (in milliseconds) to wait after starting a process before starting the next one
+    #[structopt(name = "START_DELAY (ms)", long = "start-delay", default_value = "0")]
+    pub start_delay: u64,
+
+    /// Specify how many processes may be starting at the same time. A process with a readiness probe is starting until it is ready
+    #[structopt(name = "MAX_PARALLEL_STARTS", long = "max-parallel-starts")]
+    pub max_parallel_starts: Option<usize>,
+
+    /// Specify the interval (in seconds) to print the memory and CPU usage of each process
+    #[structopt(name = "USAGE_INTERVAL (sec)", long = "usage-interval")]
+    pub usage_interval: Option<u64>,
+
+    /// Specify what to do on SIGHUP: forward it to all processes, restart all processes, reload the Procfile and .env, or restart each process type one instance at a time
+    #[structopt(
+        name = "SIGHUP",
+        long = "sighup",
+        default_value = "forward",
+        possible_values = &["forward", "restart", "reload", "rolling"]
+    )]
+    pub sighup: String,
+
+    /// Reload the Procfile and .env when they change
+    #[structopt(name = "RELOAD", long = "reload")]
+    pub is_reload: bool,
+
+    /// Run as the init process of a container: reap orphaned processes and forward signals to all processes
+    #[structopt(name = "INIT", long = "init")]
+    pub is_init: bool,
+
     /// Include timestamp in output
     #[structopt(name = "NOTIMESTAMP", short = "n", long = "no-timestamp")]
     pub is_no_timestamp: bool,
@@ -62,76 +170,305 @@ pub fn run(opts: StartOpts) -> Result<(), Box<dyn std::error::Error>> {
     let mut proc_handles = vec![];
     let procs: Arc<Mutex<Vec<Arc<Mutex<process::Process>>>>> = Arc::new(Mutex::new(vec![]));
 
-    let procfile = read_procfile(opts.procfile_path).expect("failed read Procfile");
-    // Read the formation from the command line option and always call it before process_len for the convenience of setting concurrency
-    procfile.set_concurrency(&opts.formation);
+    // Each app with its Procfile and config, in the order they were given
+    let mut apps = vec![];
+    let mut first_index = 0;
+    for mut app in load_apps(&opts)? {
+        let procfile = read_procfile(app.paths.procfile.clone()).expect("failed read Procfile");
+        if app.name.is_some() && !procfile.is_valid_formation(&app.formation) {
+            return Err(format!(
+                "{} does not have every process in formation {}",
+                app.paths.procfile.display(),
+                app.formation
+            )
+            .into());
+        }
+        procfile.set_concurrency(&app.formation);
+        let config = read_config(app.paths.config.clone())?;
+        app.first_index = first_index;
+        first_index += procfile.data.len();
+        apps.push((app, procfile, config));
+    }
+    let timeout = opts.timeout.parse::<u64>().unwrap();
+    let duration = opts.duration.as_deref().map(duration::parse).transpose()?;
 
-    let process_len = procfile.process_len();
-    let padding = procfile.padding();
+    // e.g.) api/web.1 |
+    let padding = apps
+        .iter()
+        .map(|(app, procfile, _)| procfile.padding() + app.name.as_ref().map_or(0, |n| n.len() + 1))
+        .max()
+        .unwrap_or(0);
 
-    let barrier = Arc::new(Barrier::new(process_len + 1));
-    let mut total = 0;
     let is_timestamp = !opts.is_no_timestamp;
     let display_opts = DisplayOpts {
         padding,
         is_timestamp,
     };
 
-    for (name, pe) in procfile.data.iter() {
-        let con = pe.concurrency.get();
-        let index = total;
-        let output = Arc::new(output::Output::new(index, display_opts.clone()));
-        total += 1;
+    // Settings of a process type, with the command line options for what it does not set
+    let process_config = |app: &App, config: &Config, name: &str| {
+        let mut proc_config = config.find_by(name);
+        proc_config.stop_timeout.get_or_insert(timeout);
+        proc_config.user = proc_config.user.or_else(|| opts.user.clone());
+        proc_config.group = proc_config.group.or_else(|| opts.group.clone());
+        proc_config.shell = proc_config.shell.or_else(|| opts.shell.clone());
+        proc_config.direct_exec.get_or_insert(opts.is_direct_exec);
+        proc_config.pty.get_or_insert(opts.is_pty);
+        proc_config.cwd = Some(app.paths.working_dir(proc_config.cwd.as_deref()));
+        if let Some(sandbox) = proc_config.sandbox.as_mut() {
+            sandbox.resolve(&app.paths);
+        }
+        proc_config
+    };
+    if opts.is_tmux {
+        tmux::init()?;
+    }
+    // A user, sandbox path or working directory that does not exist stops ultraman before anything has started
+    startup::check("all processes", opts.max_parallel_starts)?;
+    for (app, procfile, config) in apps.iter() {
+        for name in procfile.data.keys() {
+            let proc_config = process_config(app, config, name);
+            let process_type = app.process_type(name);
+            app::check_working_dir(&process_type, &proc_config)?;
+            readiness::check(&process_type, &proc_config)?;
+            startup::check(&process_type, proc_config.max_parallel_starts)?;
+            tmux::check(&process_type, &proc_config)?;
+            trigger::check(&process_type, &proc_config, procfile)?;
+            credentials::lookup(&proc_config)?;
+            if let Some(sandbox) = &proc_config.sandbox {
+                sandbox::check(&process_type, sandbox, &display_opts)?;
+            }
+        }
+    }
 
-        for n in 0..con {
-            let barrier = barrier.clone();
-            let procs = procs.clone();
-            let output = output.clone();
-            let process_name = name.clone();
-            let cmd = pe.command.clone();
-            let env_path = opts.env_path.clone();
-            let port = opts.port.clone();
-            let opts = display_opts.clone();
-
-            let exec_and_output_thread = process::build_exec_and_output_thread(move || {
-                let proc = Process::new(process_name, cmd, env_path, port, n, index, Some(opts));
-                let proc2 = Arc::new(Mutex::new(proc));
-                let proc3 = Arc::clone(&proc2);
-                let child_id = proc2.lock().unwrap().child.id() as i32;
-
-                output.log.output(
-                    "system",
-                    &format!(
-                        "{0:1$} start at pid: {2}",
-                        &proc2.lock().unwrap().name,
-                        padding,
-                        &child_id
-                    ),
-                );
+    if let Some(Some(name)) = &opts.attach {
+        let is_instance = apps.iter().any(|(app, procfile, _)| {
+            procfile.data.iter().any(|(t, pe)| {
+                (1..=pe.concurrency.get())
+                    .any(|n| format!("{}.{}", app.process_type(t), n) == *name)
+            })
+        });
+        if !is_instance {
+            return Err(format!("no process {} to attach stdin to", name).into());
+        }
+    }
 
-                procs.lock().unwrap().push(proc2);
-                barrier.wait();
+    // The workspace file stands for all of its apps
+    let tracked_path = opts
+        .workspace_path
+        .clone()
+        .unwrap_or_else(|| apps[0].0.paths.procfile.clone());
+    process_group::track(&tracked_path, &display_opts);
+    if opts.is_init {
+        process_group::set_subreaper(&display_opts);
+    }
 
-                output.handle_output(&proc3);
-            });
-            proc_handles.push(exec_and_output_thread);
+    reload::init(reload::Source {
+        apps: apps.iter().map(|(app, _, _)| app.clone()).collect(),
+        timeout,
+        user: opts.user.clone(),
+        group: opts.group.clone(),
+        shell: opts.shell.clone(),
+        is_direct_exec: opts.is_direct_exec,
+        is_pty: opts.is_pty,
+    });
+
+    // Children inherit NOTIFY_SOCKET, just like services started by systemd with Type=notify
+    #[cfg(target_os = "linux")]
+    {
+        let socket = notify::bind().expect("failed bind notify socket");
+        env::set_var("NOTIFY_SOCKET", &socket.path);
+        proc_handles.push(notify::build_notify_thread(
+            socket,
+            procs.clone(),
+            display_opts.clone(),
+        ));
+        // For `ultraman restart`
+        let socket = control::bind().expect("failed bind control socket");
+        proc_handles.push(control::build_control_thread(
+            socket,
+            procs.clone(),
+            display_opts.clone(),
+        ));
+        let configs = apps
+            .iter()
+            .flat_map(|(_, _, config)| config.processes.values());
+        if configs.clone().any(|c| c.watchdog.is_some()) {
+            proc_handles.push(notify::build_watchdog_thread(
+                procs.clone(),
+                display_opts.clone(),
+            ));
         }
-    }
 
-    barrier.wait();
+        if opts.usage_interval.is_some() || configs.clone().any(|c| c.usage.is_some()) {
+            proc_handles.push(usage::build_usage_thread(
+                procs.clone(),
+                opts.usage_interval.map(Duration::from_secs),
+                display_opts.clone(),
+            ));
+        }
 
-    // use handle_signal
-    let procs2 = Arc::clone(&procs);
-    let check_for_child_termination_thread =
-        process::build_check_for_child_termination_thread(procs2, display_opts.clone());
-    proc_handles.push(check_for_child_termination_thread);
+        let watches = apps
+            .iter()
+            .flat_map(|(app, procfile, config)| {
+                procfile.data.keys().filter_map(move |name| {
+                    config
+                        .find_by(name)
+                        .watch
+                        .map(|w| (app.process_type(name), w))
+                })
+            })
+            .collect::<Vec<_>>();
+        if !watches.is_empty() {
+            proc_handles.push(watch::build_watch_thread(
+                procs.clone(),
+                watches,
+                display_opts.clone(),
+            ));
+        }
 
-    let procs = Arc::clone(&procs);
+        if opts.is_reload {
+            let procs = procs.clone();
+            let display_opts = display_opts.clone();
+            let files = apps
+                .iter()
+                .flat_map(|(app, _, _)| vec![app.paths.procfile.clone(), app.paths.env.clone()])
+                .collect();
+            proc_handles.push(watch::build_file_watch_thread(files, move |file| {
+                log::system(&display_opts, &format!("{} changed, reloading", file));
+                reload::reload(&procs, &display_opts);
+            }));
+        }
+    }
+
+    // Before the processes are started, so that they get a stdin to attach to
+    if let Some(name) = &opts.attach {
+        attach::init(name.clone());
+    }
+
+    // The processes are started after the handlers, so that a slow startup can still be stopped
+    let startup = Arc::new(startup::begin(opts.max_parallel_starts));
+    proc_handles.push(process::build_check_for_child_termination_thread(
+        Arc::clone(&procs),
+        display_opts.clone(),
+    ));
     proc_handles.push(signal::handle_signal_thread(
-        procs,
-        opts.timeout.parse::<u64>().unwrap(),
-        display_opts,
+        Arc::clone(&procs),
+        signal::hup_action(&opts.sighup),
+        opts.is_init,
+        display_opts.clone(),
     ));
+    // From before the first process starts, so that a slow startup counts too
+    if let (Some(duration), Some(label)) = (duration, &opts.duration) {
+        proc_handles.push(duration::build_duration_thread(
+            duration,
+            label.clone(),
+            procs.clone(),
+            display_opts.clone(),
+        ));
+    }
+    if opts.is_tmux {
+        proc_handles.push(tmux::build_watch_thread(
+            procs.clone(),
+            display_opts.clone(),
+        ));
+    }
+
+    let mut jobs = vec![];
+    // The delay of the process type started last
+    let mut start_delay = None;
+    let mut total = 0;
+    'start: for (app, procfile, config) in apps.iter() {
+        for (name, pe) in procfile.data.iter() {
+            let con = pe.concurrency.get();
+            let index = total;
+            let output = Arc::new(output::Output::new(index, display_opts.clone()));
+            total += 1;
+            let process_type = app.process_type(name);
+
+            let proc_config = process_config(app, config, name);
+            if proc_config.schedule.is_some() {
+                jobs.push(cron::Job {
+                    name: process_type,
+                    command: pe.command.clone(),
+                    concurrency: con,
+                    index,
+                    app: app.clone(),
+                    config: proc_config,
+                });
+                continue;
+            }
+
+            for n in 0..con {
+                if let Some(delay) = start_delay.take() {
+                    thread::sleep(Duration::from_millis(delay));
+                }
+                startup.acquire(&process_type, proc_config.max_parallel_starts);
+                if signal::is_shutting_down() {
+                    break 'start;
+                }
+
+                let delay = proc_config.start_delay.unwrap_or(opts.start_delay);
+                let proc = Process::new(
+                    process_type.clone(),
+                    pe.command.clone(),
+                    app.env_for(&process_type, n, index, &proc_config)
+                        .expect("failed read .env"),
+                    n,
+                    index,
+                    Some(display_opts.clone()),
+                    proc_config.clone(),
+                );
+                let proc = Arc::new(Mutex::new(proc));
+                process::on_start(&proc, &procs, &output, &display_opts);
+
+                {
+                    let startup = Arc::clone(&startup);
+                    let procs = procs.clone();
+                    let proc = Arc::clone(&proc);
+                    let process_type = process_type.clone();
+                    thread::spawn(move || {
+                        process::wait_until_ready(&procs, &proc);
+                        startup.release(&process_type);
+                    });
+                }
+
+                let output = output.clone();
+                let procs = procs.clone();
+                proc_handles.push(process::build_exec_and_output_thread(move || {
+                    output.handle_output(&proc, &procs);
+                }));
+                start_delay = Some(delay);
+            }
+        }
+    }
+    if !jobs.is_empty() && !signal::is_shutting_down() {
+        proc_handles.push(cron::build_schedule_thread(
+            procs.clone(),
+            jobs,
+            display_opts.clone(),
+        ));
+    }
+    startup.finish();
+    // The command runs in the first app of a workspace
+    if let Some(command) = &opts.then {
+        if !signal::is_shutting_down() {
+            proc_handles.push(then::build_then_thread(
+                command.clone(),
+                apps[0].0.clone(),
+                opts.shell.clone(),
+                procs.clone(),
+                display_opts.clone(),
+            ));
+        }
+    }
+    // Once the processes are running, so that nothing typed is lost
+    if opts.attach.is_some() && !signal::is_shutting_down() {
+        proc_handles.push(attach::build_attach_thread(
+            procs.clone(),
+            display_opts.clone(),
+        ));
+    }
 
     for handle in proc_handles {
         handle.join().expect("failed join");
@@ -139,3 +476,43 @@ pub fn run(opts: StartOpts) -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// The apps of the workspace file or --app, otherwise the one app of -d, -f, -e and -c
+fn load_apps(opts: &StartOpts) -> Result<Vec<App>, Box<dyn std::error::Error>> {
+    let entries = match &opts.workspace_path {
+        Some(path) => {
+            let entries = app::read_workspace(path.clone())?;
+            if entries.is_empty() {
+                return Err(format!("{} has no apps", path.display()).into());
+            }
+            entries
+        }
+        None => opts
+            .app_roots
+            .iter()
+            .cloned()
+            .map(WorkspaceApp::at)
+            .collect(),
+    };
+    if !entries.is_empty() {
+        return Ok(app::workspace_apps(
+            entries,
+            opts.port.as_deref(),
+            &opts.formation,
+        )?);
+    }
+
+    let paths = AppPaths::resolve(
+        opts.root_path.clone(),
+        opts.procfile_path.clone(),
+        opts.env_path.clone(),
+        opts.config_path.clone(),
+    );
+    Ok(vec![App {
+        name: None,
+        paths,
+        port: opts.port.clone(),
+        formation: opts.formation.clone(),
+        first_index: 0,
+    }])
+}
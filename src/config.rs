@@ -0,0 +1,207 @@
+use crate::cron::ScheduleConfig;
+use crate::limits::{self, Rlimits};
+use crate::notify::WatchdogConfig;
+use crate::readiness::ReadinessConfig;
+use crate::sandbox::SandboxConfig;
+use crate::signal;
+use crate::trigger::TriggerConfig;
+use crate::usage::UsageConfig;
+use crate::watch::WatchConfig;
+use nix::sys::signal::Signal;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub processes: HashMap<String, ProcessConfig>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct ProcessConfig {
+    pub readiness: Option<ReadinessConfig>,
+    pub watchdog: Option<WatchdogConfig>,
+    /// Sent to stop the process, e.g. SIGQUIT for a graceful shutdown of nginx. SIGTERM if not set
+    #[serde(deserialize_with = "signal::deserialize_signal")]
+    pub stop_signal: Option<Signal>,
+    /// Seconds between the stop signal and SIGKILL. --timeout if not set
+    pub stop_timeout: Option<u64>,
+    pub watch: Option<WatchConfig>,
+    /// Milliseconds to wait after starting an instance before starting the next one. --start-delay if not set
+    pub start_delay: Option<u64>,
+    /// Instances of this type that may be starting at the same time
+    pub max_parallel_starts: Option<usize>,
+    pub schedule: Option<ScheduleConfig>,
+    pub rlimits: Option<Rlimits>,
+    /// Niceness, e.g. 10 for a low-priority indexer
+    pub nice: Option<i32>,
+    #[serde(deserialize_with = "limits::deserialize_umask")]
+    pub umask: Option<u32>,
+    /// CPUs the process may run on, Linux only
+    pub cpu_affinity: Option<Vec<usize>>,
+    pub usage: Option<UsageConfig>,
+    /// User name or uid to run the process as. --user if not set
+    pub user: Option<String>,
+    /// Group name or gid to run the process as, the primary group of the user if not set. --group if not set
+    pub group: Option<String>,
+    pub sandbox: Option<SandboxConfig>,
+    /// Shell to run the command with. --shell if not set
+    pub shell: Option<String>,
+    /// Run a command without shell metacharacters without a shell. --direct-exec if not set
+    pub direct_exec: Option<bool>,
+    /// Give the process a pseudo-terminal instead of pipes. --pty if not set
+    pub pty: Option<bool>,
+    /// Directory to run the process in, relative to the root. The root if not set
+    pub cwd: Option<PathBuf>,
+    /// Actions taken when a line of the output matches
+    pub triggers: Vec<TriggerConfig>,
+}
+
+impl Config {
+    pub fn find_by(&self, name: &str) -> ProcessConfig {
+        self.processes.get(name).cloned().unwrap_or_default()
+    }
+}
+
+// Like .env, a missing config file is not an error. It just means that no process has any extra settings.
+pub fn read_config(filepath: PathBuf) -> Result<Config, Box<dyn std::error::Error>> {
+    if !filepath.exists() {
+        return Ok(Config::default());
+    }
+
+    let file = File::open(filepath)?;
+    let config = serde_json::from_reader(file)?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::Rlimit;
+    use crate::readiness::Probe;
+    use crate::trigger::Action;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_config() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("ultraman.json");
+        let mut file = File::create(file_path.clone())?;
+        writeln!(
+            file,
+            r#"
+{{
+  "processes": {{
+    "web": {{ "readiness": {{ "type": "http", "path": "/health", "timeout": 10 }} }},
+    "worker": {{
+      "readiness": {{ "type": "output", "pattern": "booted" }},
+      "stop_signal": "SIGQUIT",
+      "stop_timeout": 30,
+      "start_delay": 500,
+      "max_parallel_starts": 2,
+      "rlimits": {{ "nofile": 65536, "core": "unlimited" }},
+      "nice": 10,
+      "umask": "027",
+      "cpu_affinity": [0, 1],
+      "user": "www-data",
+      "group": "www-data",
+      "sandbox": {{ "read_only": ["/usr"], "writable": ["./tmp"], "network": false }},
+      "shell": "/bin/bash",
+      "direct_exec": true,
+      "pty": true,
+      "cwd": "frontend",
+      "triggers": [
+        {{ "pattern": "Connection lost", "action": "restart", "cooldown": 60 }},
+        {{ "pattern": "cache stale", "action": "restart", "process": "web" }},
+        {{ "pattern": "FATAL", "action": "shutdown", "code": 2 }},
+        {{ "pattern": "deployed", "action": "run", "command": "echo $MATCHED_LINE" }}
+      ]
+    }}
+  }}
+}}
+      "#
+        )
+        .unwrap();
+
+        let result = read_config(file_path).expect("failed read config");
+
+        let web = result.find_by("web").readiness.unwrap();
+        assert_eq!(web.timeout, 10);
+        match web.probe {
+            Probe::Http { path } => assert_eq!(path, "/health"),
+            _ => panic!("unexpected probe"),
+        }
+
+        let worker = result.find_by("worker").readiness.unwrap();
+        assert_eq!(worker.timeout, 30);
+        match worker.probe {
+            Probe::Output { pattern } => assert_eq!(pattern, "booted"),
+            _ => panic!("unexpected probe"),
+        }
+
+        assert_eq!(result.find_by("worker").stop_signal, Some(Signal::SIGQUIT));
+        assert_eq!(result.find_by("worker").stop_timeout, Some(30));
+        assert_eq!(result.find_by("web").stop_signal, None);
+        assert_eq!(result.find_by("worker").start_delay, Some(500));
+        assert_eq!(result.find_by("worker").max_parallel_starts, Some(2));
+        let rlimits = result.find_by("worker").rlimits.unwrap();
+        assert_eq!(rlimits.nofile, Some(Rlimit::Limited(65536)));
+        assert_eq!(rlimits.core, Some(Rlimit::Unlimited));
+        assert_eq!(rlimits.nproc, None);
+        assert_eq!(result.find_by("worker").nice, Some(10));
+        assert_eq!(result.find_by("worker").umask, Some(0o027));
+        assert_eq!(result.find_by("worker").cpu_affinity, Some(vec![0, 1]));
+        assert_eq!(
+            result.find_by("worker").user,
+            Some(String::from("www-data"))
+        );
+        let sandbox = result.find_by("worker").sandbox.unwrap();
+        assert_eq!(sandbox.read_only, vec![PathBuf::from("/usr")]);
+        assert!(!sandbox.network);
+        assert_eq!(
+            result.find_by("worker").shell,
+            Some(String::from("/bin/bash"))
+        );
+        assert_eq!(result.find_by("worker").direct_exec, Some(true));
+        assert_eq!(result.find_by("worker").pty, Some(true));
+        assert_eq!(
+            result.find_by("worker").cwd,
+            Some(PathBuf::from("frontend"))
+        );
+
+        let triggers = result.find_by("worker").triggers;
+        assert_eq!(triggers[0].action, Action::Restart { process: None });
+        assert_eq!(triggers[0].cooldown, 60);
+        assert_eq!(
+            triggers[1].action,
+            Action::Restart {
+                process: Some(String::from("web"))
+            }
+        );
+        assert_eq!(triggers[1].cooldown, 30);
+        assert_eq!(triggers[2].action, Action::Shutdown { code: 2 });
+        assert_eq!(
+            triggers[3].action,
+            Action::Run {
+                command: String::from("echo $MATCHED_LINE")
+            }
+        );
+
+        assert!(result.find_by("clock").readiness.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_when_not_exists() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let result = read_config(dir.path().join("ultraman.json")).expect("failed read config");
+        assert!(result.processes.is_empty());
+
+        Ok(())
+    }
+}
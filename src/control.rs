@@ -0,0 +1,145 @@
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    // Rolling restart of a process type
+    Restart(String),
+}
+
+// e.g.) restart web
+pub fn parse_request(payload: &str) -> Option<Request> {
+    let mut words = payload.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("restart"), Some(name), None) => Some(Request::Restart(name.to_string())),
+        _ => None,
+    }
+}
+
+// `ultraman restart` talks to the running `ultraman start` over an abstract unix socket,
+// which relies on SO_PASSCRED like NOTIFY_SOCKET, so it is Linux only
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{parse_request, Request};
+    use crate::log;
+    use crate::opt::DisplayOpts;
+    use crate::process::{self, Process};
+
+    use nix::cmsg_space;
+    use nix::sys::socket::{
+        recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags, UnixCredentials,
+    };
+    use nix::sys::uio::IoVec;
+    use nix::unistd::{getpid, getuid};
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+
+    fn socket_name(pid: i32) -> String {
+        format!("ultraman/{}/control", pid)
+    }
+
+    pub fn bind() -> Result<UnixDatagram, Box<dyn std::error::Error>> {
+        let addr = SocketAddr::from_abstract_name(socket_name(getpid().as_raw()).as_bytes())?;
+        let socket = UnixDatagram::bind_addr(&addr)?;
+        // Abstract sockets have no file permissions, so the sender's uid is checked instead
+        setsockopt(socket.as_raw_fd(), sockopt::PassCred, &true)?;
+        Ok(socket)
+    }
+
+    pub fn send(pid: i32, request: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let addr = SocketAddr::from_abstract_name(socket_name(pid).as_bytes())?;
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(request.as_bytes(), &addr)?;
+        Ok(())
+    }
+
+    pub fn build_control_thread(
+        socket: UnixDatagram,
+        procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        opts: DisplayOpts,
+    ) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(String::from("handle control"))
+            .spawn(move || loop {
+                let mut buf = [0u8; 1024];
+                let mut cmsg = cmsg_space!(UnixCredentials);
+                let (bytes, uid) = match recvmsg(
+                    socket.as_raw_fd(),
+                    &[IoVec::from_mut_slice(&mut buf)],
+                    Some(&mut cmsg),
+                    MsgFlags::empty(),
+                ) {
+                    Ok(msg) => (
+                        msg.bytes,
+                        msg.cmsgs().find_map(|c| match c {
+                            ControlMessageOwned::ScmCredentials(cred) => Some(cred.uid()),
+                            _ => None,
+                        }),
+                    ),
+                    Err(_) => continue,
+                };
+                if uid != Some(getuid().as_raw()) {
+                    continue;
+                }
+
+                let payload = String::from_utf8_lossy(&buf[..bytes]).to_string();
+                if let Some(request) = parse_request(&payload) {
+                    handle_request(request, &procs, &opts);
+                }
+            })
+            .expect("failed handle control")
+    }
+
+    fn handle_request(
+        request: Request,
+        procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        opts: &DisplayOpts,
+    ) {
+        match request {
+            Request::Restart(name) => {
+                let is_running = procs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .any(|p| p.lock().unwrap().process_type() == name);
+                if !is_running {
+                    log::system(
+                        opts,
+                        &format!("rolling restart requested for unknown process {}", name),
+                    );
+                    return;
+                }
+
+                let procs = Arc::clone(procs);
+                let opts = opts.clone();
+                thread::spawn(move || process::rolling_restart(&procs, &name, &opts));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_parse_request() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_request("restart web"),
+            Some(Request::Restart(String::from("web")))
+        );
+        assert_eq!(
+            parse_request("restart web\n"),
+            Some(Request::Restart(String::from("web")))
+        );
+        assert_eq!(parse_request("restart"), None);
+        assert_eq!(parse_request("restart web worker"), None);
+        assert_eq!(parse_request("stop web"), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use crate::config::ProcessConfig;
+
+use nix::unistd::{getgid, getuid, setgid, setgroups, setuid, Gid, Group, Uid, User};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Who a process runs as
+#[derive(Debug)]
+pub struct Credentials {
+    pub uid: Uid,
+    pub gid: Gid,
+    // Supplementary groups, None to keep those of ultraman when only the group is changed
+    pub groups: Option<Vec<Gid>>,
+    // For HOME, USER and LOGNAME
+    pub user: Option<(String, PathBuf)>,
+}
+
+// e.g.) www-data or 33
+pub fn find_user(name: &str) -> Result<User, String> {
+    let user = match name.parse::<u32>() {
+        Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+        Err(_) => User::from_name(name),
+    };
+    user.map_err(|e| format!("failed look up user {}: {}", name, e))?
+        .ok_or_else(|| format!("user {} does not exist", name))
+}
+
+// e.g.) www-data or 33
+pub fn find_group(name: &str) -> Result<Group, String> {
+    let group = match name.parse::<u32>() {
+        Ok(gid) => Group::from_gid(Gid::from_raw(gid)),
+        Err(_) => Group::from_name(name),
+    };
+    group
+        .map_err(|e| format!("failed look up group {}: {}", name, e))?
+        .ok_or_else(|| format!("group {} does not exist", name))
+}
+
+// The user and group the process runs as, None when it runs as ultraman does
+pub fn lookup(config: &ProcessConfig) -> Result<Option<Credentials>, String> {
+    let user = config.user.as_deref().map(find_user).transpose()?;
+    let group = config.group.as_deref().map(find_group).transpose()?;
+    if user.is_none() && group.is_none() {
+        return Ok(None);
+    }
+
+    let uid = user.as_ref().map_or_else(getuid, |u| u.uid);
+    let gid = match (&group, &user) {
+        (Some(g), _) => g.gid,
+        (None, Some(u)) => u.gid,
+        (None, None) => getgid(),
+    };
+    if !getuid().is_root() {
+        if uid == getuid() && gid == getgid() {
+            return Ok(None);
+        }
+        let name = config.user.as_deref().or(config.group.as_deref()).unwrap();
+        return Err(format!("running processes as {} needs root", name));
+    }
+
+    let groups = match &user {
+        Some(u) => Some(supplementary_groups(u, gid)?),
+        None => None,
+    };
+    Ok(Some(Credentials {
+        uid,
+        gid,
+        groups,
+        user: user.map(|u| (u.name, u.dir)),
+    }))
+}
+
+// The groups the user is a member of in /etc/group, like `initgroups`
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn supplementary_groups(user: &User, gid: Gid) -> Result<Vec<Gid>, String> {
+    let name = std::ffi::CString::new(user.name.as_str()).map_err(|e| e.to_string())?;
+    nix::unistd::getgrouplist(&name, gid)
+        .map_err(|e| format!("failed look up groups of {}: {}", user.name, e))
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn supplementary_groups(_user: &User, gid: Gid) -> Result<Vec<Gid>, String> {
+    Ok(vec![gid])
+}
+
+// Switch the child to the user and group of the process before exec, and give it the HOME of the user.
+// start looks up every process beforehand, so a missing user stops it before anything has started.
+pub fn prepare(command: &mut Command, config: &ProcessConfig) {
+    let credentials = match lookup(config).expect("failed look up user") {
+        Some(c) => c,
+        None => return,
+    };
+    if let Some((name, home)) = &credentials.user {
+        command
+            .env("HOME", home)
+            .env("USER", name)
+            .env("LOGNAME", name);
+    }
+
+    unsafe {
+        command.pre_exec(move || {
+            // The groups first, as they can't be changed anymore after setuid
+            if let Some(groups) = &credentials.groups {
+                setgroups(groups).map_err(|_| io::Error::last_os_error())?;
+            }
+            setgid(credentials.gid).map_err(|_| io::Error::last_os_error())?;
+            setuid(credentials.uid).map_err(|_| io::Error::last_os_error())?;
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_lookup() -> anyhow::Result<()> {
+        let config: ProcessConfig = serde_json::from_str(r#"{ "user": "ultraman-no-such-user" }"#)?;
+        assert_eq!(
+            lookup(&config).unwrap_err(),
+            "user ultraman-no-such-user does not exist"
+        );
+
+        let config: ProcessConfig =
+            serde_json::from_str(r#"{ "group": "ultraman-no-such-group" }"#)?;
+        assert_eq!(
+            lookup(&config).unwrap_err(),
+            "group ultraman-no-such-group does not exist"
+        );
+
+        assert!(lookup(&ProcessConfig::default()).unwrap().is_none());
+
+        // The current user by uid
+        let config: ProcessConfig =
+            serde_json::from_str(&format!(r#"{{ "user": "{}" }}"#, getuid()))?;
+        if let Some(credentials) = lookup(&config).unwrap() {
+            assert_eq!(credentials.uid, getuid());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+use crate::app::App;
+use crate::config::ProcessConfig;
+use crate::log;
+use crate::opt::DisplayOpts;
+use crate::output::Output;
+use crate::process::{self, Process};
+use crate::signal;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone};
+use serde::de::{self, Deserialize, Deserializer};
+use serde_derive::Deserialize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScheduleConfig {
+    /// e.g. "*/5 * * * *" or "@daily", in local time
+    #[serde(deserialize_with = "deserialize_schedule")]
+    pub cron: Schedule,
+    #[serde(default)]
+    pub overlap: Overlap,
+}
+
+// What to do when it is time to run again while the previous run is still running
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Overlap {
+    #[default]
+    Skip,
+    // Run once more as soon as the previous run exits
+    Queue,
+    Allow,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // As in cron, a day matches either field when both are restricted
+    is_any_day: bool,
+    is_any_weekday: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// e.g.) minute hour day-of-month month day-of-week
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression = expression.trim();
+        let expanded = match expression {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            e => e,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Do not support schedule {}, it should have 5 fields",
+                expression
+            ));
+        }
+        let field = |n: usize, min: u32, max: u32, names: &[&str], offset: u32| {
+            parse_field(fields[n], min, max, names, offset)
+                .map_err(|e| format!("Do not support schedule {}, {}", expression, e))
+        };
+
+        let mut weekdays = field(4, 0, 7, &WEEKDAYS, 0)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Schedule {
+            expression: expression.to_string(),
+            minutes: field(0, 0, 59, &[], 0)?,
+            hours: field(1, 0, 23, &[], 0)?,
+            days: field(2, 1, 31, &[], 0)?,
+            months: field(3, 1, 12, &MONTHS, 1)?,
+            weekdays,
+            is_any_day: fields[2].starts_with('*'),
+            is_any_weekday: fields[4].starts_with('*'),
+        })
+    }
+}
+
+// e.g.) *, 5, 1-5, */15, 0-30/10, mon-fri, 1,15
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    offset: u32,
+) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        if let Ok(v) = s.parse::<u32>() {
+            return Ok(v);
+        }
+        names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(s))
+            .map(|p| p as u32 + offset)
+            .ok_or_else(|| format!("{} is not a valid value", s))
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(s) if s > 0 => (range, s),
+                _ => return Err(format!("{} is not a valid step", step)),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else {
+            let v = value(range)?;
+            // e.g.) 5/15 is every 15 from 5
+            (v, if step > 1 { max } else { v })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{} is out of range {}-{}", range, min, max));
+        }
+        for v in (start..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+pub fn deserialize_schedule<'de, D>(deserializer: D) -> Result<Schedule, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let expression = String::deserialize(deserializer)?;
+    expression.parse::<Schedule>().map_err(de::Error::custom)
+}
+
+impl Schedule {
+    pub fn as_str(&self) -> &str {
+        &self.expression
+    }
+
+    // The first scheduled minute after the given time. None if it never comes, e.g. 0 0 30 2 *
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut date = after.naive_local().date();
+        // Every combination of day of month, month and weekday repeats within 28 years
+        for _ in 0..366 * 28 {
+            if self.is_day_match(date) {
+                for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                    for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                        let naive = date.and_hms_opt(hour, minute, 0)?;
+                        // A time skipped by DST does not exist, and a repeated one runs once
+                        match Local.from_local_datetime(&naive).earliest() {
+                            Some(t) if t > after => return Some(t),
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn is_day_match(&self, date: NaiveDate) -> bool {
+        let is_month = self.months & (1 << date.month()) != 0;
+        let is_day = self.days & (1 << date.day()) != 0;
+        let is_weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+
+        is_month
+            && if self.is_any_day || self.is_any_weekday {
+                is_day && is_weekday
+            } else {
+                is_day || is_weekday
+            }
+    }
+}
+
+// With scheduled process types, no running process does not mean that all have terminated
+static IS_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_scheduled() -> bool {
+    IS_SCHEDULED.load(Ordering::SeqCst)
+}
+
+// A process type that is started at each scheduled time instead of at startup
+pub struct Job {
+    pub name: String,
+    pub command: String,
+    pub concurrency: usize,
+    pub index: usize,
+    pub app: App,
+    pub config: ProcessConfig,
+}
+
+struct State {
+    job: Job,
+    output: Arc<Output>,
+    next: Option<DateTime<Local>>,
+    is_queued: bool,
+}
+
+pub fn build_schedule_thread(
+    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    jobs: Vec<Job>,
+    opts: DisplayOpts,
+) -> JoinHandle<()> {
+    IS_SCHEDULED.store(true, Ordering::SeqCst);
+
+    thread::Builder::new()
+        .name(String::from("run schedule"))
+        .spawn(move || {
+            let mut states = jobs
+                .into_iter()
+                .map(|job| {
+                    let schedule = &job.config.schedule.as_ref().unwrap().cron;
+                    let next = schedule.next_after(Local::now());
+                    log::system(
+                        &opts,
+                        &format!(
+                            "{} scheduled at {}, next run at {}",
+                            job.name,
+                            schedule.as_str(),
+                            format_next(next)
+                        ),
+                    );
+                    State {
+                        output: Arc::new(Output::new(job.index, opts.clone())),
+                        job,
+                        next,
+                        is_queued: false,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            loop {
+                // Read before looking at the processes, so that an exit in between still wakes us up
+                let reaped = process::reaped_count();
+                if signal::is_shutting_down() {
+                    return;
+                }
+
+                let now = Local::now();
+                for state in states.iter_mut() {
+                    let is_running = procs
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .any(|p| p.lock().unwrap().process_type() == state.job.name);
+
+                    if state.is_queued && !is_running {
+                        state.is_queued = false;
+                        run(&state.job, &state.output, &procs, &opts);
+                        continue;
+                    }
+                    match state.next {
+                        Some(next) if next <= now => (),
+                        _ => continue,
+                    }
+
+                    let overlap = &state.job.config.schedule.as_ref().unwrap().overlap;
+                    state.next = state
+                        .job
+                        .config
+                        .schedule
+                        .as_ref()
+                        .unwrap()
+                        .cron
+                        .next_after(now);
+                    match overlap {
+                        Overlap::Skip if is_running => log::system(
+                            &opts,
+                            &format!(
+                                "{} is still running, skipping this run. Next run at {}",
+                                state.job.name,
+                                format_next(state.next)
+                            ),
+                        ),
+                        Overlap::Queue if is_running => {
+                            if !state.is_queued {
+                                log::system(
+                                    &opts,
+                                    &format!(
+                                        "{} is still running, running again when it exits",
+                                        state.job.name
+                                    ),
+                                );
+                            }
+                            state.is_queued = true;
+                        }
+                        _ => run(&state.job, &state.output, &procs, &opts),
+                    }
+                }
+
+                // Wake up at least every minute to follow changes of the system clock
+                let wait = states
+                    .iter()
+                    .filter_map(|s| s.next)
+                    .min()
+                    .map(|next| (next - Local::now()).to_std().unwrap_or_default())
+                    .unwrap_or(Duration::from_secs(60))
+                    .min(Duration::from_secs(60));
+                process::wait_for_reap(reaped, Some(Instant::now() + wait));
+            }
+        })
+        .expect("failed run schedule")
+}
+
+fn run(
+    job: &Job,
+    output: &Arc<Output>,
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    opts: &DisplayOpts,
+) {
+    for n in 0..job.concurrency {
+        let env = match job.app.env_for(&job.name, n, job.index, &job.config) {
+            Ok(env) => env,
+            Err(e) => {
+                log::system(
+                    opts,
+                    &format!(
+                        "{} could not read its env, skipping this run: {}",
+                        job.name, e
+                    ),
+                );
+                return;
+            }
+        };
+        let proc = Process::new(
+            job.name.clone(),
+            job.command.clone(),
+            env,
+            n,
+            job.index,
+            Some(opts.clone()),
+            job.config.clone(),
+        );
+        let proc = Arc::new(Mutex::new(proc));
+        process::on_start(&proc, procs, output, opts);
+
+        let output = Arc::clone(output);
+        let procs = Arc::clone(procs);
+        process::build_exec_and_output_thread(move || {
+            output.handle_output(&proc, &procs);
+        });
+    }
+}
+
+fn format_next(next: Option<DateTime<Local>>) -> String {
+    next.map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| String::from("never"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    fn local(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Local> {
+        let naive = NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap();
+        Local.from_local_datetime(&naive).earliest().unwrap()
+    }
+
+    fn next(expression: &str, after: DateTime<Local>) -> DateTime<Local> {
+        expression
+            .parse::<Schedule>()
+            .unwrap()
+            .next_after(after)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_schedule() -> anyhow::Result<()> {
+        assert!("*/5 * * * *".parse::<Schedule>().is_ok());
+        assert!("0 9 * * mon-fri".parse::<Schedule>().is_ok());
+        assert!("0 0 1 jan,jul *".parse::<Schedule>().is_ok());
+        assert!("@daily".parse::<Schedule>().is_ok());
+
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+        assert!("* * * * someday".parse::<Schedule>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_after() -> anyhow::Result<()> {
+        // 2021-01-01 is a Friday
+        let friday = local(2021, 1, 1, 10, 7);
+
+        assert_eq!(next("*/15 * * * *", friday), local(2021, 1, 1, 10, 15));
+        assert_eq!(next("5/15 * * * *", friday), local(2021, 1, 1, 10, 20));
+        assert_eq!(next("@hourly", friday), local(2021, 1, 1, 11, 0));
+        assert_eq!(next("@daily", friday), local(2021, 1, 2, 0, 0));
+        // Not the same minute again
+        assert_eq!(next("7 10 * * *", friday), local(2021, 1, 2, 10, 7));
+        // Over the weekend
+        assert_eq!(next("0 9 * * mon-fri", friday), local(2021, 1, 4, 9, 0));
+        // 7 is Sunday too
+        assert_eq!(next("0 9 * * 7", friday), local(2021, 1, 3, 9, 0));
+        // Either the 15th or a Monday
+        assert_eq!(next("0 0 15 * mon", friday), local(2021, 1, 4, 0, 0));
+        assert_eq!(next("0 0 1 feb *", friday), local(2021, 2, 1, 0, 0));
+
+        assert!("0 0 30 2 *"
+            .parse::<Schedule>()
+            .unwrap()
+            .next_after(friday)
+            .is_none());
+
+        Ok(())
+    }
+}
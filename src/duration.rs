@@ -0,0 +1,104 @@
+use crate::log;
+use crate::opt::DisplayOpts;
+use crate::process::{self, Process};
+use crate::signal;
+
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// e.g.) 90s, 10m, 2h or 1h30m, and 45 for 45 seconds
+pub fn parse(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {}, e.g. 90s, 10m or 1h30m", value);
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    if value.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut secs: u64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n = number.parse::<u64>().map_err(|_| invalid())?;
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            _ => return Err(invalid()),
+        };
+        secs = n
+            .checked_mul(unit)
+            .and_then(|s| secs.checked_add(s))
+            .ok_or_else(invalid)?;
+        number.clear();
+    }
+    // e.g.) 1h30
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+// With --duration, e.g. for a soak test, everything is stopped as on SIGINT once the time is up.
+// The exit code is 0 unless a process failed before, e.g. was killed by the OOM killer.
+pub fn build_duration_thread(
+    duration: Duration,
+    // e.g.) 10m as given
+    label: String,
+    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    opts: DisplayOpts,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("limit duration"))
+        .spawn(move || {
+            thread::sleep(duration);
+            if signal::is_shutting_down() {
+                return;
+            }
+
+            let reason = format!("ran for {}, starting shutdown", label);
+            let code = match process::failure() {
+                Some((name, code)) => {
+                    log::system(
+                        &opts,
+                        &format!("{}, {} failed earlier with code {}", reason, name, code),
+                    );
+                    *code
+                }
+                None => {
+                    log::system(&opts, &reason);
+                    0
+                }
+            };
+            signal::shutdown(procs, code, opts);
+        })
+        .expect("failed limit duration")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        assert_eq!(parse("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse("").is_err());
+        assert!(parse("10x").is_err());
+        assert!(parse("1h30").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("18446744073709551615h").is_err());
+        assert!(parse("18446744073709551615s1s").is_err());
+
+        Ok(())
+    }
+}
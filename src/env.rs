@@ -9,7 +9,7 @@ pub fn read_env(filepath: PathBuf) -> Result<Env, Box<dyn std::error::Error>> {
 
     if let Some(iter) = dotenv::from_path_iter(filepath.as_path()).ok() {
         for item in iter {
-            let (key, val) = item.expect("Could not convert .env to tuple");
+            let (key, val) = item?;
             env.insert(key, val);
         }
         return Ok(env);
@@ -46,4 +46,16 @@ PS=1
 
         Ok(())
     }
+
+    #[test]
+    fn test_read_env_malformed() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join(".env");
+        let mut file = File::create(file_path.clone())?;
+        writeln!(file, r#"FOO="broken"#)?;
+
+        assert!(read_env(file_path).is_err());
+
+        Ok(())
+    }
 }
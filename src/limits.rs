@@ -0,0 +1,180 @@
+use crate::config::ProcessConfig;
+
+use nix::libc;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_derive::Deserialize;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Rlimits {
+    /// Open files
+    pub nofile: Option<Rlimit>,
+    /// Processes of the user
+    pub nproc: Option<Rlimit>,
+    /// Address space in bytes
+    #[serde(rename = "as")]
+    pub address_space: Option<Rlimit>,
+    /// Core file size in bytes
+    pub core: Option<Rlimit>,
+}
+
+// A number or "unlimited"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rlimit {
+    Limited(u64),
+    Unlimited,
+}
+
+impl<'de> Deserialize<'de> for Rlimit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(n) => Ok(Rlimit::Limited(n)),
+            Raw::Text(t) if t == "unlimited" || t == "infinity" => Ok(Rlimit::Unlimited),
+            Raw::Text(t) => Err(de::Error::custom(format!(
+                "Do not support limit {}, it should be a number or unlimited",
+                t
+            ))),
+        }
+    }
+}
+
+impl Rlimit {
+    // Each format has its own word for no limit, e.g. infinity for systemd
+    pub fn format(&self, unlimited: &str) -> String {
+        match self {
+            Rlimit::Limited(n) => n.to_string(),
+            Rlimit::Unlimited => unlimited.to_string(),
+        }
+    }
+}
+
+impl Rlimits {
+    // e.g.) [("nofile", Limited(65536))]
+    pub fn entries(&self) -> Vec<(&'static str, Rlimit)> {
+        vec![
+            ("nofile", self.nofile),
+            ("nproc", self.nproc),
+            ("as", self.address_space),
+            ("core", self.core),
+        ]
+        .into_iter()
+        .filter_map(|(name, limit)| limit.map(|l| (name, l)))
+        .collect()
+    }
+}
+
+// e.g.) "027". JSON has no octal numbers, so it is a string
+pub fn deserialize_umask<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let umask = String::deserialize(deserializer)?;
+    match u32::from_str_radix(&umask, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(Some(mode)),
+        _ => Err(de::Error::custom(format!(
+            "Do not support umask {}, it should be octal like 022",
+            umask
+        ))),
+    }
+}
+
+fn resource(name: &str) -> i32 {
+    (match name {
+        "nofile" => libc::RLIMIT_NOFILE,
+        "nproc" => libc::RLIMIT_NPROC,
+        "as" => libc::RLIMIT_AS,
+        _ => libc::RLIMIT_CORE,
+    }) as i32
+}
+
+// Apply the limits, niceness, umask and CPU affinity of the process in the child, before exec.
+// Like `ulimit -n`, both the soft and hard limits are set.
+pub fn prepare(command: &mut Command, config: &ProcessConfig) {
+    // Everything is computed here, as the child must not allocate between fork and exec
+    let rlimits = config
+        .rlimits
+        .as_ref()
+        .map(|r| r.entries())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, limit)| {
+            let value = match limit {
+                Rlimit::Limited(n) => n as libc::rlim_t,
+                Rlimit::Unlimited => libc::RLIM_INFINITY,
+            };
+            (resource(name), value)
+        })
+        .collect::<Vec<_>>();
+    let nice = config.nice;
+    let umask = config.umask;
+    #[cfg(target_os = "linux")]
+    let cpu_set = config.cpu_affinity.as_ref().map(|cpus| {
+        let mut set = nix::sched::CpuSet::new();
+        for cpu in cpus {
+            let _ = set.set(*cpu);
+        }
+        set
+    });
+
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, value) in rlimits.iter() {
+                let rlimit = libc::rlimit {
+                    rlim_cur: *value,
+                    rlim_max: *value,
+                };
+                if libc::setrlimit(*resource as _, &rlimit) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(umask) = umask {
+                libc::umask(umask as libc::mode_t);
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(cpu_set) = &cpu_set {
+                nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), cpu_set)
+                    .map_err(|_| io::Error::last_os_error())?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_prepare() -> anyhow::Result<()> {
+        let config: ProcessConfig =
+            serde_json::from_str(r#"{ "rlimits": { "nofile": 256, "core": 0 }, "umask": "027" }"#)?;
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("ulimit -n; ulimit -c; umask");
+        prepare(&mut command, &config);
+        let output = String::from_utf8(command.output()?.stdout)?;
+
+        assert_eq!(output.lines().collect::<Vec<_>>(), vec!["256", "0", "0027"]);
+
+        Ok(())
+    }
+}
@@ -47,6 +47,20 @@ pub fn output(proc_name: &str, content: &str, index: Option<usize>, opt: &LogOpt
     log.output(proc_name, content)
 }
 
+// A line of ultraman itself rather than of a process
+pub fn system(opts: &DisplayOpts, content: &str) {
+    output(
+        "system",
+        content,
+        None,
+        &LogOpt {
+            is_color: false,
+            padding: opts.padding,
+            is_timestamp: opts.is_timestamp,
+        },
+    )
+}
+
 pub fn error(proc_name: &str, err: &dyn std::error::Error, is_padding: bool, opt: &LogOpt) {
     let content = &format!("error: {:?}", err);
     if is_padding {
@@ -1,15 +1,36 @@
 use opt::{Opt, Ultraman};
 use structopt::StructOpt;
 
+mod app;
+mod attach;
 mod cmd;
+mod config;
+mod control;
+mod credentials;
+mod cron;
+mod duration;
 mod env;
+mod limits;
 mod log;
+mod notify;
 mod opt;
 mod output;
 mod process;
+mod process_group;
 mod procfile;
+mod pty;
+mod readiness;
+mod reload;
+mod sandbox;
+mod shell;
 mod signal;
+mod startup;
 mod stream_read;
+mod then;
+mod tmux;
+mod trigger;
+mod usage;
+mod watch;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::from_args();
@@ -19,6 +40,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             Ultraman::Start(opts) => cmd::start::run(opts).expect("failed ultraman start"),
             Ultraman::Run(opts) => cmd::run::run(opts),
             Ultraman::Export(opts) => cmd::export::run(opts).expect("failed ultraman export"),
+            Ultraman::Restart(opts) => cmd::restart::run(opts).expect("failed ultraman restart"),
         }
     }
 
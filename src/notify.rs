@@ -0,0 +1,257 @@
+use serde_derive::Deserialize;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WatchdogConfig {
+    /// Seconds without WATCHDOG=1 before the process is considered hung. Passed to it as WATCHDOG_USEC
+    pub timeout: u64,
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchdogAction {
+    #[default]
+    Restart,
+    Fail,
+}
+
+// e.g.) READY=1\nSTATUS=Listening on 5000
+pub fn parse_message(payload: &str) -> Vec<(&str, &str)> {
+    payload
+        .lines()
+        .filter_map(|line| {
+            let mut kv = line.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => Some((key, value)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// NOTIFY_SOCKET relies on SO_PASSCRED and abstract unix sockets, so it is Linux only
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{parse_message, WatchdogAction};
+    use crate::log;
+    use crate::opt::DisplayOpts;
+    use crate::process::{self, Process, ProcessState};
+    use crate::signal;
+
+    use nix::cmsg_space;
+    use nix::sys::socket::{
+        recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags, UnixCredentials,
+    };
+    use nix::sys::uio::IoVec;
+    use nix::unistd::getpid;
+    use std::fs;
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, sleep, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    const WATCHDOG_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub struct NotifySocket {
+        socket: UnixDatagram,
+        // The value of NOTIFY_SOCKET. "@" means the abstract namespace, so there is no file to clean up
+        pub path: String,
+    }
+
+    pub fn bind() -> Result<NotifySocket, Box<dyn std::error::Error>> {
+        let name = format!("ultraman/{}/notify", getpid());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+        let socket = UnixDatagram::bind_addr(&addr)?;
+        // Needed to know which process sent the message
+        setsockopt(socket.as_raw_fd(), sockopt::PassCred, &true)?;
+
+        Ok(NotifySocket {
+            socket,
+            path: format!("@{}", name),
+        })
+    }
+
+    pub fn build_notify_thread(
+        socket: NotifySocket,
+        procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        opts: DisplayOpts,
+    ) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(String::from("handle notify"))
+            .spawn(move || loop {
+                let mut buf = [0u8; 4096];
+                let mut cmsg = cmsg_space!(UnixCredentials);
+                let (bytes, sender) = match recvmsg(
+                    socket.socket.as_raw_fd(),
+                    &[IoVec::from_mut_slice(&mut buf)],
+                    Some(&mut cmsg),
+                    MsgFlags::empty(),
+                ) {
+                    Ok(msg) => (
+                        msg.bytes,
+                        msg.cmsgs().find_map(|c| match c {
+                            ControlMessageOwned::ScmCredentials(cred) => Some(cred.pid()),
+                            _ => None,
+                        }),
+                    ),
+                    Err(_) => continue,
+                };
+
+                let proc = match sender.and_then(|pid| find_by_pid(&procs, pid)) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let payload = String::from_utf8_lossy(&buf[..bytes]).to_string();
+                handle_message(&proc, &payload, &opts);
+            })
+            .expect("failed handle notify")
+    }
+
+    fn handle_message(proc: &Arc<Mutex<Process>>, payload: &str, opts: &DisplayOpts) {
+        let mut proc = proc.lock().unwrap();
+
+        for (key, value) in parse_message(payload) {
+            match (key, value) {
+                ("READY", "1") => {
+                    if proc.is_ready {
+                        continue;
+                    }
+                    proc.is_ready = true;
+                    proc.watchdog_at.get_or_insert(Instant::now());
+                    // With a readiness probe, the probe thread reports it
+                    if proc.config.readiness.is_none() {
+                        log::system(
+                            opts,
+                            &format!(
+                                "{0:1$} ready after {2:.1}s",
+                                &proc.name,
+                                opts.padding,
+                                proc.started_at.elapsed().as_secs_f64()
+                            ),
+                        );
+                    }
+                }
+                ("STATUS", status) => log::system(
+                    opts,
+                    &format!("{0:1$} status: {2}", &proc.name, opts.padding, status),
+                ),
+                ("WATCHDOG", "1") => proc.watchdog_at = Some(Instant::now()),
+                // Ask for the watchdog action right away
+                ("WATCHDOG", "trigger") => {
+                    if let Some(watchdog) = &proc.config.watchdog {
+                        proc.watchdog_at =
+                            Instant::now().checked_sub(Duration::from_secs(watchdog.timeout));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // The sender may be a descendant of the tracked process (e.g. $SHELL -c "cmd1; cmd2"),
+    // so walk up the parents until a tracked pid is found.
+    fn find_by_pid(
+        procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        pid: i32,
+    ) -> Option<Arc<Mutex<Process>>> {
+        let mut pid = pid;
+        while pid > 1 {
+            let found = procs
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|p| p.lock().unwrap().child.id() as i32 == pid)
+                .cloned();
+            if found.is_some() {
+                return found;
+            }
+            pid = parent_pid(pid)?;
+        }
+        None
+    }
+
+    fn parent_pid(pid: i32) -> Option<i32> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // e.g.) 1234 (sh) S 1000 ...
+        let after_comm = &stat[stat.rfind(')')? + 1..];
+        after_comm.split_whitespace().nth(1)?.parse::<i32>().ok()
+    }
+
+    pub fn build_watchdog_thread(
+        procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        opts: DisplayOpts,
+    ) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(String::from("check watchdog"))
+            .spawn(move || loop {
+                sleep(WATCHDOG_INTERVAL);
+
+                let expired = procs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|p| is_expired(&p.lock().unwrap()))
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                for proc in expired {
+                    let (name, watchdog) = {
+                        let proc = proc.lock().unwrap();
+                        (proc.name.clone(), proc.config.watchdog.clone().unwrap())
+                    };
+                    log::system(
+                        &opts,
+                        &format!(
+                            "{0:1$} watchdog timeout, no WATCHDOG=1 for {2}s",
+                            &name, opts.padding, watchdog.timeout
+                        ),
+                    );
+
+                    if watchdog.action == WatchdogAction::Restart {
+                        let opts = opts.clone();
+                        thread::spawn(move || process::restart(&proc, &opts));
+                    } else {
+                        signal::shutdown(Arc::clone(&procs), 1, opts.clone());
+                        return;
+                    }
+                }
+            })
+            .expect("failed check watchdog")
+    }
+
+    fn is_expired(proc: &Process) -> bool {
+        match &proc.config.watchdog {
+            Some(watchdog) if proc.state == ProcessState::Running => {
+                // Like systemd, the watchdog starts once the process is ready
+                if proc.config.readiness.is_some() && !proc.is_ready {
+                    return false;
+                }
+                let last = proc.watchdog_at.unwrap_or(proc.started_at);
+                last.elapsed() >= Duration::from_secs(watchdog.timeout)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message() -> anyhow::Result<()> {
+        let result = parse_message("READY=1\nSTATUS=Listening on 5000\nbroken\n");
+        assert_eq!(
+            result,
+            vec![("READY", "1"), ("STATUS", "Listening on 5000")]
+        );
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::cmd::export::ExportOpts;
+use crate::cmd::restart::RestartOpts;
 use crate::cmd::run::RunOpts;
 use crate::cmd::start::StartOpts;
 use structopt::{clap, StructOpt};
@@ -31,6 +32,12 @@ pub enum Ultraman {
         about = "Export the application to another process management format"
     )]
     Export(ExportOpts),
+
+    #[structopt(
+        name = "restart",
+        about = "Restart a process type of the running application one instance at a time"
+    )]
+    Restart(RestartOpts),
 }
 
 ///// Options not related to commands /////
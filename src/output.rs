@@ -1,7 +1,11 @@
 use crate::log::{Log, LogOpt, Printable};
 use crate::opt::DisplayOpts;
 use crate::process::Process;
+use crate::pty;
+use crate::readiness;
 use crate::stream_read::{PipeError, PipeStreamReader, PipedLine};
+use crate::tmux;
+use crate::trigger;
 
 use crossbeam_channel::Select;
 use std::sync::{Arc, Mutex};
@@ -24,33 +28,62 @@ impl Output {
         }
     }
 
-    pub fn handle_output(&self, proc: &Arc<Mutex<Process>>) {
+    pub fn handle_output(
+        &self,
+        proc: &Arc<Mutex<Process>>,
+        procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    ) {
+        // The process writes to its tmux window instead
+        if tmux::is_enabled() {
+            return;
+        }
         let mut channels: Vec<PipeStreamReader> = Vec::new();
-        channels.push(PipeStreamReader::new(Box::new(
-            proc.lock()
-                .unwrap()
-                .child
-                .stdout
-                .take()
-                .expect("failed take stdout"),
-        )));
-        channels.push(PipeStreamReader::new(Box::new(
-            proc.lock()
-                .unwrap()
-                .child
-                .stderr
-                .take()
-                .expect("failed take stderr"),
-        )));
+        let pty = proc
+            .lock()
+            .unwrap()
+            .pty
+            .as_ref()
+            .map(|master| master.try_clone().expect("failed clone pty"));
+        let is_pty = pty.is_some();
+        match pty {
+            // stdout and stderr are the same terminal
+            Some(master) => channels.push(PipeStreamReader::new(Box::new(pty::Reader(master)))),
+            None => {
+                channels.push(PipeStreamReader::new(Box::new(
+                    proc.lock()
+                        .unwrap()
+                        .child
+                        .stdout
+                        .take()
+                        .expect("failed take stdout"),
+                )));
+                channels.push(PipeStreamReader::new(Box::new(
+                    proc.lock()
+                        .unwrap()
+                        .child
+                        .stderr
+                        .take()
+                        .expect("failed take stderr"),
+                )));
+            }
+        }
+
+        let ready_pattern = readiness::output_pattern(&proc.lock().unwrap().config);
+        // check has reported an invalid pattern already, so the process runs without triggers
+        let triggers = {
+            let proc = proc.lock().unwrap();
+            trigger::compile(proc.process_type(), &proc.config).unwrap_or_default()
+        };
 
         let mut select = Select::new();
         for channel in channels.iter() {
             select.recv(&channel.lines);
         }
 
-        let mut stream_eof = false;
+        // Both stdout and stderr are read to the end, so that the last lines before an exit are not lost
+        let mut open_streams = channels.len();
 
-        while !stream_eof {
+        while open_streams > 0 {
             let operation = select.select();
             let index = operation.index();
             let received = operation.recv(
@@ -64,11 +97,21 @@ impl Output {
             match received {
                 Ok(remote_result) => match remote_result {
                     Ok(piped_line) => match piped_line {
-                        PipedLine::Line(line) => {
+                        PipedLine::Line(mut line) => {
+                            // The terminal ends each line with \r\n
+                            if is_pty && line.ends_with('\r') {
+                                line.pop();
+                            }
                             log.output(&proc.lock().unwrap().name, &line);
+                            if let Some(re) = &ready_pattern {
+                                if re.is_match(&line) {
+                                    proc.lock().unwrap().is_ready = true;
+                                }
+                            }
+                            trigger::handle(&triggers, &line, proc, procs);
                         }
                         PipedLine::EOF => {
-                            stream_eof = true;
+                            open_streams -= 1;
                             select.remove(index);
                         }
                     },
@@ -78,7 +121,7 @@ impl Output {
                     },
                 },
                 Err(_) => {
-                    stream_eof = true;
+                    open_streams -= 1;
                     select.remove(index);
                 }
             }
@@ -104,8 +147,12 @@ impl Output {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ProcessConfig;
+    use crate::env::Env;
+    use crate::process::ProcessState;
     use anyhow;
     use std::process::{Command, Stdio};
+    use std::time::Instant;
 
     #[test]
     fn test_handle_output() -> anyhow::Result<()> {
@@ -118,6 +165,16 @@ mod tests {
                 .spawn()
                 .expect("failed execute handle_output command"),
             opts: None,
+            command: String::new(),
+            env: Env::new(),
+            config: ProcessConfig::default(),
+            started_at: Instant::now(),
+            is_ready: false,
+            is_restarting: false,
+            is_removing: false,
+            watchdog_at: None,
+            pty: None,
+            state: ProcessState::Running,
         }));
 
         let proc2 = Arc::clone(&proc);
@@ -128,7 +185,7 @@ mod tests {
                 is_timestamp: true,
             },
         );
-        output.handle_output(&proc2);
+        output.handle_output(&proc2, &Arc::new(Mutex::new(vec![])));
 
         Ok(())
     }
@@ -1,61 +1,516 @@
-use crate::env::read_env;
+use crate::attach;
+use crate::config::ProcessConfig;
+use crate::credentials;
+use crate::cron;
+use crate::env::{read_env, Env};
+use crate::limits;
 use crate::log::{self, LogOpt};
 use crate::opt::DisplayOpts;
+use crate::output::Output;
+use crate::process_group;
+use crate::pty;
+use crate::readiness;
+use crate::sandbox;
+use crate::shell;
 use crate::signal;
+use crate::startup;
+use crate::then;
+use crate::tmux;
+use crossbeam_channel::{unbounded, Sender};
 use nix::sys::signal::Signal;
 use nix::sys::wait::WaitStatus;
 use nix::{self, unistd::Pid};
+use signal_hook::{iterator::Signals, SIGCHLD};
 use std::env::{self as os_env};
+use std::fs::File;
+use std::io;
 use std::path::PathBuf;
 use std::process::{Child, Command, Stdio};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[cfg(not(test))]
 use std::process::exit;
 
+// running -> stopping -> stopped, or -> killed when it does not stop in time.
+// A process can exit on its own at any point, so every step has to expect that it is already gone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProcessState {
+    Running,
+    // The stop signal was sent, SIGKILL follows at the deadline
+    Stopping { deadline: Instant },
+    Stopped,
+    Killed,
+}
+
 pub struct Process {
     pub index: usize,
     pub name: String,
     pub child: Child,
     pub opts: Option<DisplayOpts>,
+    pub command: String,
+    pub env: Env,
+    pub config: ProcessConfig,
+    pub started_at: Instant,
+    pub is_ready: bool,
+    pub is_restarting: bool,
+    // Stopped for good without shutting down the others, e.g. removed from the Procfile
+    pub is_removing: bool,
+    pub watchdog_at: Option<Instant>,
+    // The master of the pseudo-terminal of the child, with --pty
+    pub pty: Option<File>,
+    pub state: ProcessState,
 }
 
 impl Process {
+    // env is from env_for
     pub fn new(
         process_name: String,
         cmd: String,
-        env_path: PathBuf,
-        port: Option<String>,
+        read_env: Env,
         concurrency_index: usize,
         index: usize,
         opts: Option<DisplayOpts>,
+        config: ProcessConfig,
     ) -> Self {
-        let mut read_env = read_env(env_path.clone()).expect("failed read .env");
-        read_env.insert(
-            String::from("PORT"),
-            port_for(env_path, port, index, concurrency_index),
+        let name = ps_for(process_name, concurrency_index + 1);
+        let (child, pty) = spawn_child(&name, &cmd, &read_env, &config);
+        Process {
+            index,
+            name,
+            child,
+            opts,
+            command: cmd,
+            env: read_env,
+            config,
+            started_at: Instant::now(),
+            is_ready: false,
+            is_restarting: false,
+            is_removing: false,
+            watchdog_at: None,
+            pty,
+            state: ProcessState::Running,
+        }
+    }
+
+    // Replace the terminated child with a new one that runs the same command with the same env
+    pub fn respawn(&mut self) {
+        let (child, pty) = spawn_child(&self.name, &self.command, &self.env, &self.config);
+        self.child = child;
+        self.pty = pty;
+        self.started_at = Instant::now();
+        self.is_ready = false;
+        self.is_restarting = false;
+        self.watchdog_at = None;
+        self.state = ProcessState::Running;
+    }
+
+    // e.g.) web for web.1
+    pub fn process_type(&self) -> &str {
+        self.name.rsplit_once('.').map_or(&self.name, |(t, _)| t)
+    }
+
+    // e.g.) 0 for web.1
+    pub fn concurrency_index(&self) -> usize {
+        let number = self.name.rsplit_once('.').map_or("1", |(_, n)| n);
+        number.parse::<usize>().unwrap_or(1) - 1
+    }
+
+    pub fn stop_signal(&self) -> Signal {
+        self.config.stop_signal.unwrap_or(Signal::SIGTERM)
+    }
+
+    // start fills in --timeout for processes without their own stop_timeout
+    pub fn stop_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.stop_timeout.unwrap_or(5))
+    }
+
+    // Send the stop signal once. Calling it again while stopping does not push the deadline back.
+    pub fn stop(&mut self, opts: &DisplayOpts) {
+        if self.state != ProcessState::Running {
+            return;
+        }
+        self.state = ProcessState::Stopping {
+            deadline: Instant::now() + self.stop_timeout(),
+        };
+        self.send_signal(self.stop_signal(), opts);
+    }
+
+    pub fn kill(&mut self, opts: &DisplayOpts) {
+        if self.state == ProcessState::Stopped || self.state == ProcessState::Killed {
+            return;
+        }
+        self.state = ProcessState::Killed;
+        self.send_signal(Signal::SIGKILL, opts);
+    }
+
+    pub fn is_past_deadline(&self) -> bool {
+        match self.state {
+            ProcessState::Stopping { deadline } => Instant::now() >= deadline,
+            _ => false,
+        }
+    }
+
+    fn send_signal(&self, signal: Signal, opts: &DisplayOpts) {
+        let log_opt = LogOpt {
+            is_color: false,
+            padding: opts.padding,
+            is_timestamp: opts.is_timestamp,
+        };
+        log::system(
+            opts,
+            &format!(
+                "sending {3} for {0:1$} at pid {2}",
+                &self.name,
+                opts.padding,
+                self.child.id(),
+                signal.as_str()
+            ),
         );
-        read_env.insert(
-            String::from("PS"),
-            ps_for(process_name.clone(), concurrency_index + 1),
+        match process_group::kill(self.child.id(), signal) {
+            // It has already exited and is waiting to be reaped
+            Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => (),
+            Err(e) => log::error("system", &e, true, &log_opt),
+        }
+    }
+}
+
+// The env a process is started with: .env plus PORT, PS and what its config asks for
+pub fn env_for(
+    process_name: &str,
+    env_path: PathBuf,
+    port: Option<String>,
+    concurrency_index: usize,
+    index: usize,
+    config: &ProcessConfig,
+) -> Result<Env, Box<dyn std::error::Error>> {
+    let mut env = read_env(env_path)?;
+    let port = port_in(&env, port, index, concurrency_index);
+    env.insert(String::from("PORT"), port);
+    env.insert(
+        String::from("PS"),
+        ps_for(process_name.to_string(), concurrency_index + 1),
+    );
+    if let Some(watchdog) = &config.watchdog {
+        env.insert(
+            String::from("WATCHDOG_USEC"),
+            (watchdog.timeout * 1_000_000).to_string(),
         );
-        let shell = os_env::var("SHELL").expect("$SHELL is not set");
+    }
+    Ok(env)
+}
 
-        Process {
-            index,
-            name: ps_for(process_name, concurrency_index + 1),
-            child: Command::new(shell)
-                .arg("-c")
-                .arg(cmd)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .envs(read_env)
-                .spawn()
-                .expect("failed execute command"),
+fn spawn_child(name: &str, cmd: &str, env: &Env, config: &ProcessConfig) -> (Child, Option<File>) {
+    let mut command = shell::command(
+        cmd,
+        config.shell.as_deref(),
+        config.direct_exec.unwrap_or(false),
+    );
+    let (pty, slave) = if tmux::is_enabled() {
+        // Its output is in the window, not in ultraman's
+        let pane = tmux::open_pane(name).expect("failed open tmux window");
+        command
+            .stdin(pane.try_clone().expect("failed clone tmux window"))
+            .stdout(pane.try_clone().expect("failed clone tmux window"))
+            .stderr(pane);
+        (None, None)
+    } else if config.pty.unwrap_or(false) {
+        let (master, slave) = pty::open().expect("failed open pty");
+        (Some(master), Some(slave))
+    } else {
+        // Only ultraman writes to the stdin of a process, and only with --attach
+        let stdin = if attach::is_enabled() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+        command
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        (None, None)
+    };
+    // The limits may need root, and PR_SET_PDEATHSIG is cleared when the user changes
+    limits::prepare(&mut command, config);
+    credentials::prepare(&mut command, config);
+    sandbox::prepare(&mut command, config);
+    process_group::prepare(&mut command);
+    if let Some(slave) = slave {
+        pty::prepare(&mut command, slave).expect("failed prepare pty");
+    }
+    // After HOME and USER of the user, so that .env can still set them
+    command.envs(env);
+    if let Some(cwd) = &config.cwd {
+        command.current_dir(cwd);
+    }
+
+    let child = spawn(command).expect("failed execute command");
+    process_group::record(child.id());
+    tmux::record(name, child.id());
+    (child, pty)
+}
+
+type SpawnRequest = (Command, Sender<io::Result<Child>>);
+
+// PR_SET_PDEATHSIG fires when the thread that spawned the child exits, not when ultraman does.
+// So every child is spawned from one thread that lives as long as ultraman.
+fn spawn(command: Command) -> io::Result<Child> {
+    static SPAWNER: OnceLock<Sender<SpawnRequest>> = OnceLock::new();
+    let spawner = SPAWNER.get_or_init(|| {
+        let (tx, rx) = unbounded::<SpawnRequest>();
+        thread::Builder::new()
+            .name(String::from("spawn children"))
+            .spawn(move || {
+                for (mut command, reply) in rx {
+                    let _ = reply.send(command.spawn());
+                }
+            })
+            .expect("failed spawn children");
+        tx
+    });
+
+    let (tx, rx) = unbounded();
+    spawner
+        .send((command, tx))
+        .expect("failed send spawn request");
+    rx.recv().expect("failed receive spawned child")
+}
+
+// Log the start, register the process as running and begin probing its readiness
+pub fn on_start(
+    proc: &Arc<Mutex<Process>>,
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    output: &Arc<Output>,
+    opts: &DisplayOpts,
+) {
+    let (name, child_id, has_readiness) = {
+        let proc = proc.lock().unwrap();
+        (
+            proc.name.clone(),
+            proc.child.id(),
+            proc.config.readiness.is_some(),
+        )
+    };
+
+    output.log.output(
+        "system",
+        &format!("{0:1$} start at pid: {2}", &name, opts.padding, &child_id),
+    );
+
+    procs.lock().unwrap().push(Arc::clone(proc));
+    // It exited before it was added, so check_for_child_termination has to look at it again
+    if UNCLAIMED
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(_, s)| s.pid() == Some(Pid::from_raw(child_id as i32)))
+    {
+        let _ = nix::sys::signal::raise(Signal::SIGCHLD);
+    }
+    // Started while the shutdown was stopping the others, so it was not among them
+    if signal::is_shutting_down() {
+        proc.lock().unwrap().stop(opts);
+    }
+
+    if has_readiness {
+        readiness::build_readiness_thread(
+            Arc::clone(proc),
+            Arc::clone(procs),
+            Arc::clone(output),
+            opts.clone(),
+        );
+    }
+}
+
+pub fn respawn(proc: &Arc<Mutex<Process>>, procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>) {
+    let (index, opts) = {
+        let mut proc = proc.lock().unwrap();
+        proc.respawn();
+        (proc.index, proc.opts.clone().unwrap_or_default())
+    };
+    let output = Arc::new(Output::new(index, opts.clone()));
+
+    on_start(proc, procs, &output, &opts);
+
+    let proc = Arc::clone(proc);
+    let procs = Arc::clone(procs);
+    build_exec_and_output_thread(move || {
+        output.handle_output(&proc, &procs);
+    });
+}
+
+// Stop the process and let check_for_child_termination start it again.
+// If it does not stop within its stop timeout, it is killed.
+pub fn restart(proc: &Arc<Mutex<Process>>, opts: &DisplayOpts) {
+    let (child_id, deadline) = {
+        let mut proc = proc.lock().unwrap();
+        if proc.state != ProcessState::Running {
+            return;
+        }
+        proc.is_restarting = true;
+        proc.stop(opts);
+        (proc.child.id(), Instant::now() + proc.stop_timeout())
+    };
+
+    while Instant::now() < deadline {
+        let reaped = reaped_count();
+        if proc.lock().unwrap().child.id() != child_id {
+            return;
+        }
+        wait_for_reap(reaped, Some(deadline));
+    }
+
+    let mut proc = proc.lock().unwrap();
+    // Shutdown may have taken over in the meantime, or it has been respawned already
+    if proc.child.id() == child_id && proc.is_restarting {
+        proc.kill(opts);
+    }
+}
+
+// Restart the instances of a process type one at a time, so that the others keep serving.
+// The next one is restarted once the previous one is running again, or ready if it has a probe.
+pub fn rolling_restart(
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    process_type: &str,
+    opts: &DisplayOpts,
+) {
+    static ROLLING: Mutex<Vec<String>> = Mutex::new(vec![]);
+    {
+        let mut rolling = ROLLING.lock().unwrap();
+        if rolling.iter().any(|t| t == process_type) {
+            log::system(
+                opts,
+                &format!("rolling restart of {} is already in progress", process_type),
+            );
+            return;
+        }
+        rolling.push(process_type.to_string());
+    }
+
+    let mut instances = procs
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|p| p.lock().unwrap().process_type() == process_type)
+        .cloned()
+        .collect::<Vec<_>>();
+    instances.sort_by_key(|p| p.lock().unwrap().concurrency_index());
+
+    let started_at = Instant::now();
+    let total = instances.len();
+    for (i, proc) in instances.iter().enumerate() {
+        if signal::is_shutting_down() {
+            break;
+        }
+        let (name, child_id) = {
+            let proc = proc.lock().unwrap();
+            (proc.name.clone(), proc.child.id())
+        };
+        log::system(
             opts,
+            &format!(
+                "rolling restart of {}: restarting {} ({}/{})",
+                process_type,
+                name,
+                i + 1,
+                total
+            ),
+        );
+
+        restart(proc, opts);
+        if !wait_until_restarted(procs, proc, child_id) {
+            break;
         }
     }
+
+    if signal::is_shutting_down() {
+        log::system(
+            opts,
+            &format!("rolling restart of {} aborted by shutdown", process_type),
+        );
+    } else {
+        log::system(
+            opts,
+            &format!(
+                "rolling restart of {} finished in {:.1}s",
+                process_type,
+                started_at.elapsed().as_secs_f64()
+            ),
+        );
+    }
+    ROLLING.lock().unwrap().retain(|t| t != process_type);
+}
+
+// Returns false if the process will not come back, e.g. because of a shutdown
+fn wait_until_restarted(
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    proc: &Arc<Mutex<Process>>,
+    child_id: u32,
+) -> bool {
+    loop {
+        let reaped = reaped_count();
+        if proc.lock().unwrap().child.id() != child_id {
+            break;
+        }
+        if signal::is_shutting_down() {
+            return false;
+        }
+        wait_for_reap(reaped, Some(Instant::now() + Duration::from_secs(1)));
+    }
+
+    wait_until_ready(procs, proc)
+}
+
+// Returns once a process with a readiness probe is ready, right away for one without.
+// Returns false if it terminated or a shutdown started first.
+pub fn wait_until_ready(
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    proc: &Arc<Mutex<Process>>,
+) -> bool {
+    if proc.lock().unwrap().config.readiness.is_none() {
+        return true;
+    }
+    // A process that never gets ready fails the whole app through its readiness thread
+    loop {
+        if proc.lock().unwrap().is_ready {
+            return true;
+        }
+        let is_running = procs.lock().unwrap().iter().any(|p| Arc::ptr_eq(p, proc));
+        if !is_running || signal::is_shutting_down() {
+            return false;
+        }
+        thread::sleep(readiness::PROBE_INTERVAL);
+    }
+}
+
+// Stop the process for good, without shutting down the others.
+// If it does not stop within its stop timeout, it is killed.
+pub fn remove(proc: &Arc<Mutex<Process>>, opts: &DisplayOpts) {
+    let deadline = {
+        let mut proc = proc.lock().unwrap();
+        if proc.state != ProcessState::Running {
+            return;
+        }
+        proc.is_removing = true;
+        proc.stop(opts);
+        Instant::now() + proc.stop_timeout()
+    };
+
+    loop {
+        let reaped = reaped_count();
+        {
+            let mut proc = proc.lock().unwrap();
+            if !matches!(proc.state, ProcessState::Stopping { .. }) {
+                return;
+            }
+            if Instant::now() >= deadline {
+                proc.kill(opts);
+                return;
+            }
+        }
+        wait_for_reap(reaped, Some(deadline));
+    }
 }
 
 // https://stackoverflow.com/questions/34439977/lifetime-of-variables-passed-to-a-new-thread
@@ -71,24 +526,133 @@ where
         .expect("failed exec and output")
 }
 
+// Bumped every time a child is reaped, so that others can wait for it instead of polling
+static REAPED: (Mutex<u64>, Condvar) = (Mutex::new(0), Condvar::new());
+
+pub fn reaped_count() -> u64 {
+    *REAPED.0.lock().unwrap()
+}
+
+// Returns once a child has been reaped since `since` was read, or at the deadline.
+// Reading the count before checking the condition means a reap in between is not missed.
+pub fn wait_for_reap(since: u64, deadline: Option<Instant>) {
+    let (count, condvar) = &REAPED;
+    let mut count = count.lock().unwrap();
+    while *count == since {
+        count = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return;
+                }
+                condvar.wait_timeout(count, deadline - now).unwrap().0
+            }
+            None => condvar.wait(count).unwrap(),
+        };
+    }
+}
+
+// The first process that terminated on its own with a non-zero code or by a signal, with that code
+// or 128 plus the signal
+static FAILURE: OnceLock<(String, i32)> = OnceLock::new();
+
+pub fn failure() -> Option<&'static (String, i32)> {
+    FAILURE.get()
+}
+
+// Children reaped before they were added to the list, e.g. a command that exits right away.
+// on_start adds them later, so their status is kept until then.
+static UNCLAIMED: Mutex<Vec<(Instant, WaitStatus)>> = Mutex::new(Vec::new());
+// Orphans reaped in --init mode are never claimed, so only recent ones are kept,
+// before their pid is used again
+const UNCLAIMED_TTL: Duration = Duration::from_secs(5);
+
+fn is_tracked(procs: &[Arc<Mutex<Process>>], pid: Pid) -> bool {
+    procs
+        .iter()
+        .any(|p| p.lock().unwrap().child.id() as i32 == pid.as_raw())
+}
+
+// Under the lock of the list, so that on_start either adds the process first or finds it unclaimed
+fn claim(procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, status: WaitStatus) -> bool {
+    let procs = procs.lock().unwrap();
+    match status.pid() {
+        Some(pid) if is_tracked(&procs, pid) => true,
+        _ => {
+            let mut unclaimed = UNCLAIMED.lock().unwrap();
+            unclaimed.retain(|(at, _)| at.elapsed() < UNCLAIMED_TTL);
+            unclaimed.push((Instant::now(), status));
+            false
+        }
+    }
+}
+
+// The status of a child that is not a process, e.g. the command of --then
+pub fn take_unclaimed(pid: u32) -> Option<WaitStatus> {
+    let mut unclaimed = UNCLAIMED.lock().unwrap();
+    let i = unclaimed
+        .iter()
+        .position(|(_, s)| s.pid() == Some(Pid::from_raw(pid as i32)))?;
+    Some(unclaimed.remove(i).1)
+}
+
+// Waits for a child that is not a process, e.g. a readiness probe command. The thread that reaps
+// every child gets its status, so Child::wait would fail with ECHILD.
+// Returns None if it is still running at the deadline.
+pub fn wait_unclaimed(pid: u32, deadline: Option<Instant>) -> Option<WaitStatus> {
+    loop {
+        let reaped = reaped_count();
+        if let Some(status) = take_unclaimed(pid) {
+            return Some(status);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None;
+        }
+        wait_for_reap(reaped, deadline);
+    }
+}
+
+// The statuses of children that on_start has added since they were reaped
+fn take_claimed(procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>) -> Vec<WaitStatus> {
+    let procs = procs.lock().unwrap();
+    let mut unclaimed = UNCLAIMED.lock().unwrap();
+    let (claimed, rest): (Vec<_>, Vec<_>) = unclaimed
+        .drain(..)
+        .partition(|(_, s)| s.pid().is_some_and(|pid| is_tracked(&procs, pid)));
+    *unclaimed = rest;
+    claimed.into_iter().map(|(_, s)| s).collect()
+}
+
+fn notify_reaped() {
+    let (count, condvar) = &REAPED;
+    *count.lock().unwrap() += 1;
+    condvar.notify_all();
+}
+
 pub fn build_check_for_child_termination_thread(
     procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
     opts: DisplayOpts,
 ) -> JoinHandle<()> {
+    // Registered before the first check, so that a child exiting in between still wakes us up
+    let signals = Signals::new([SIGCHLD]).expect("failed trap SIGCHLD");
+
     thread::Builder::new()
         .name(String::from(format!("check child terminated")))
-        .spawn(move || {
-            loop {
-                // Waiting for the end of any one child process
-                let procs2 = Arc::clone(&procs);
-                let procs3 = Arc::clone(&procs);
-                if let Some((_, code)) = check_for_child_termination(procs2, opts.clone()) {
-                    signal::kill_children(procs3, Signal::SIGTERM, code, opts.clone())
-                }
-                // check_for_child_termination returns immediately, so let's sleep
-                // a little to avoid pegging CPU.
-                std::thread::sleep(std::time::Duration::from_millis(10));
+        .spawn(move || loop {
+            let procs2 = Arc::clone(&procs);
+            let procs3 = Arc::clone(&procs);
+            if let Some((_, code)) = check_for_child_termination(procs2, opts.clone()) {
+                let opts = opts.clone();
+                // Keep reaping while the others are stopping
+                #[cfg(not(test))]
+                thread::spawn(move || signal::shutdown(procs3, code, opts));
+                #[cfg(test)]
+                signal::shutdown(procs3, code, opts);
             }
+
+            // Sleep until the next child terminates. Several children may share one SIGCHLD,
+            // so check_for_child_termination reaps all that have terminated.
+            signals.wait().for_each(drop);
         })
         .expect("failed check child terminated")
 }
@@ -97,7 +661,12 @@ pub fn check_for_child_termination(
     procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
     opts: DisplayOpts,
 ) -> Option<(Pid, i32)> {
-    let child_termination_fn = Box::new(move |pid: Pid, message: &str| {
+    let procs2 = Arc::clone(&procs);
+    let procs3 = Arc::clone(&procs);
+    // Returns true if a tracked process has terminated for good. In --init mode pids that
+    // were never started by ultraman (re-parented orphans) are reaped here too and ignored.
+    let child_termination_fn = Box::new(move |pid: Pid, message: &str, code: i32| {
+        let mut terminated = None;
         procs.lock().unwrap().retain(|p| {
             let child_id = p.lock().unwrap().child.id() as i32;
             if Pid::from_raw(child_id) == pid {
@@ -114,38 +683,100 @@ pub fn check_for_child_termination(
                         is_timestamp: opts.is_timestamp,
                     },
                 );
+                terminated = Some(Arc::clone(p));
             }
             Pid::from_raw(child_id) != pid
         });
+
+        let proc = match terminated {
+            Some(p) => p,
+            None => return false,
+        };
+        // Nothing should outlive the process, e.g. the server started by `npm run dev`
+        let _ = process_group::kill(pid.as_raw() as u32, Signal::SIGKILL);
+        process_group::forget(pid.as_raw() as u32);
+
+        let (is_restarting, is_removing, is_scheduled) = {
+            let mut proc = proc.lock().unwrap();
+            // Not stopped by ultraman, e.g. a crash or the OOM killer
+            if proc.state == ProcessState::Running && code != 0 {
+                let _ = FAILURE.set((proc.name.clone(), code));
+            }
+            if proc.state != ProcessState::Killed {
+                proc.state = ProcessState::Stopped;
+            }
+            (
+                proc.is_restarting,
+                proc.is_removing,
+                proc.config.schedule.is_some(),
+            )
+        };
+        if is_removing {
+            return false;
+        }
+        // A process stopped by restart is started again instead of shutting down all processes
+        if is_restarting && !signal::is_shutting_down() {
+            respawn(&proc, &procs2);
+            return false;
+        }
+        // A scheduled run is expected to exit, and the next one is started by the schedule
+        !is_scheduled
     });
 
-    // Waiting for the end of any one child process
-    match nix::sys::wait::waitpid(
-        Pid::from_raw(-1),
-        Some(nix::sys::wait::WaitPidFlag::WNOHANG),
-    ) {
-        Ok(exit_status) => match exit_status {
+    // Reap every child that has terminated, but report only the first exit
+    let mut exited = None;
+    let mut handle_status = |status: WaitStatus| {
+        match status {
             WaitStatus::Exited(pid, code) => {
-                child_termination_fn(pid, &format!("exited with code {}", code));
-                return Some((pid, code));
+                if child_termination_fn(pid, &format!("exited with code {}", code), code) {
+                    exited.get_or_insert((pid, code));
+                }
             }
             WaitStatus::Signaled(pid, signal, _) => {
-                child_termination_fn(pid, &format!("terminated by {}", signal.as_str()));
-                return None;
-            }
-            _ => return None,
-        },
-        Err(e) => {
-            if let nix::Error::Sys(nix::errno::Errno::ECHILD) = e {
-                // close loop (thread finished)
-                #[cfg(not(test))]
-                exit(0);
-                #[cfg(test)]
-                panic!("exit 0");
+                child_termination_fn(
+                    pid,
+                    &format!("terminated by {}", signal.as_str()),
+                    128 + signal as i32,
+                );
             }
-            return None;
+            _ => return,
         }
+        notify_reaped();
     };
+    for status in take_claimed(&procs3) {
+        handle_status(status);
+    }
+    // Reap until StillAlive or ECHILD, as there is nothing more to reap for now
+    while let Ok(status @ (WaitStatus::Exited(..) | WaitStatus::Signaled(..))) =
+        nix::sys::wait::waitpid(
+            Pid::from_raw(-1),
+            Some(nix::sys::wait::WaitPidFlag::WNOHANG),
+        )
+    {
+        if claim(&procs3, status) {
+            handle_status(status);
+        } else {
+            // Someone may be waiting for it with take_unclaimed
+            notify_reaped();
+        }
+    }
+
+    // Every process has terminated without a shutdown, e.g. all were killed by signals.
+    // Orphans re-parented in --init mode keep waitpid from failing with ECHILD, so the list decides.
+    if exited.is_none()
+        && !signal::is_shutting_down()
+        && !startup::is_starting()
+        && !cron::is_scheduled()
+        && !then::is_running()
+        && procs3.lock().unwrap().is_empty()
+    {
+        // close loop (thread finished)
+        #[cfg(not(test))]
+        exit(0);
+        #[cfg(test)]
+        panic!("exit 0");
+    }
+    exited
 }
 
 fn ps_for(process_name: String, concurrency: usize) -> String {
@@ -158,13 +789,16 @@ pub fn port_for(
     index: usize,
     concurrency: usize,
 ) -> String {
-    let result =
-        base_port(env_path, port).parse::<usize>().unwrap() + index * 100 + concurrency - 1;
+    let env = read_env(env_path).unwrap();
+    port_in(&env, port, index, concurrency)
+}
+
+fn port_in(env: &Env, port: Option<String>, index: usize, concurrency: usize) -> String {
+    let result = base_port(env, port).parse::<usize>().unwrap() + index * 100 + concurrency - 1;
     result.to_string()
 }
 
-fn base_port(env_path: PathBuf, port: Option<String>) -> String {
-    let env = read_env(env_path).unwrap();
+fn base_port(env: &Env, port: Option<String>) -> String {
     let default_port = String::from("5000");
 
     if let Some(p) = port {
@@ -193,6 +827,16 @@ mod tests {
                     .spawn()
                     .expect("failed execute check_for_child_termination_thread-1"),
                 opts: None,
+                command: String::new(),
+                env: Env::new(),
+                config: ProcessConfig::default(),
+                started_at: Instant::now(),
+                is_ready: false,
+                is_restarting: false,
+                is_removing: false,
+                watchdog_at: None,
+                pty: None,
+                state: ProcessState::Running,
             })),
             Arc::new(Mutex::new(Process {
                 index: 1,
@@ -201,6 +845,16 @@ mod tests {
                     .spawn()
                     .expect("failed execute check_for_child_termination_thread-2"),
                 opts: None,
+                command: String::new(),
+                env: Env::new(),
+                config: ProcessConfig::default(),
+                started_at: Instant::now(),
+                is_ready: false,
+                is_restarting: false,
+                is_removing: false,
+                watchdog_at: None,
+                pty: None,
+                state: ProcessState::Running,
             })),
         ]));
         let procs2 = Arc::clone(&procs);
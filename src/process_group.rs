@@ -0,0 +1,389 @@
+use crate::log::{self, LogOpt};
+use crate::opt::DisplayOpts;
+
+use nix::libc;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::{getpid, getppid, getuid, setsid, Pid};
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+// Process groups started by this run, so the next run can clean them up if ultraman crashes
+static PGID_FILE: OnceLock<Mutex<PgidFile>> = OnceLock::new();
+
+// The first entry is ultraman itself, followed by the groups that are still running
+struct PgidFile {
+    file: File,
+    entries: Vec<String>,
+}
+
+impl PgidFile {
+    // Rewritten as a whole, so that the groups that have terminated do not pile up
+    fn write(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        for entry in self.entries.iter() {
+            writeln!(self.file, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+// Run the child in its own session (and so its own process group) and tie its life to ultraman
+pub fn prepare(command: &mut Command) {
+    let parent = getpid();
+    unsafe {
+        command.pre_exec(move || {
+            setsid().map_err(|_| io::Error::last_os_error())?;
+
+            #[cfg(target_os = "linux")]
+            {
+                nix::libc::prctl(nix::libc::PR_SET_PDEATHSIG, nix::libc::SIGKILL);
+                // ultraman died before prctl took effect
+                if getppid() != parent {
+                    nix::libc::_exit(1);
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = parent;
+
+            Ok(())
+        });
+    }
+}
+
+// The child is the session leader, so its pid is also the process group id
+pub fn kill(pid: u32, signal: Signal) -> nix::Result<()> {
+    signal::killpg(Pid::from_raw(pid as i32), signal)
+}
+
+// Orphaned descendants are re-parented to ultraman instead of the real init, so they can be reaped.
+// As PID 1 of a container this is a given, but not when ultraman runs under tini or docker --init.
+#[cfg(target_os = "linux")]
+pub fn set_subreaper(opts: &DisplayOpts) {
+    if unsafe { nix::libc::prctl(nix::libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        log::error(
+            "system",
+            &io::Error::last_os_error(),
+            true,
+            &LogOpt {
+                is_color: false,
+                padding: opts.padding,
+                is_timestamp: opts.is_timestamp,
+            },
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_subreaper(_opts: &DisplayOpts) {}
+
+pub fn track(procfile_path: &Path, opts: &DisplayOpts) {
+    // Without a place only the user can write to, the processes are not tracked
+    let path = match pgid_file_path(procfile_path) {
+        Ok(path) => path,
+        Err(e) => {
+            log::system(opts, &e.to_string());
+            return;
+        }
+    };
+    cleanup_stale(&path, opts);
+
+    let file = match OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&path)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            log::system(
+                opts,
+                &format!(
+                    "failed create {}, processes are not tracked: {}",
+                    path.display(),
+                    e
+                ),
+            );
+            return;
+        }
+    };
+    let mut pgid_file = PgidFile {
+        file,
+        entries: vec![entry(getpid().as_raw())],
+    };
+    if let Err(e) = pgid_file.write() {
+        log::system(
+            opts,
+            &format!(
+                "failed write {}, processes are not tracked: {}",
+                path.display(),
+                e
+            ),
+        );
+        return;
+    }
+    let _ = PGID_FILE.set(Mutex::new(pgid_file));
+}
+
+// The pid of the ultraman that is running the Procfile, if any
+pub fn owner(procfile_path: &Path) -> Option<i32> {
+    let content = read_pgid_file(&pgid_file_path(procfile_path).ok()?)?;
+    let (owner, start) = parse_entry(content.lines().next()?)?;
+    Some(owner).filter(|_| is_recorded(owner, start))
+}
+
+pub fn record(pid: u32) {
+    if let Some(file) = PGID_FILE.get() {
+        let mut file = file.lock().unwrap();
+        file.entries.push(entry(pid as i32));
+        let _ = file.write();
+    }
+}
+
+// Called once the group has been killed, so it is not left to the next run
+pub fn forget(pid: u32) {
+    if let Some(file) = PGID_FILE.get() {
+        let mut file = file.lock().unwrap();
+        file.entries
+            .retain(|e| parse_entry(e).map(|(p, _)| p) != Some(pid as i32));
+        let _ = file.write();
+    }
+}
+
+// e.g.) /run/user/1000/ultraman-1234567890.pgids, or /tmp/ultraman-1000/ultraman-1234567890.pgids
+fn pgid_file_path(procfile_path: &Path) -> io::Result<PathBuf> {
+    let procfile_path = fs::canonicalize(procfile_path).unwrap_or(procfile_path.to_path_buf());
+    let mut hasher = DefaultHasher::new();
+    procfile_path.hash(&mut hasher);
+    Ok(runtime_dir()?.join(format!("ultraman-{}.pgids", hasher.finish())))
+}
+
+// Only the user can create files in it, so nobody else can plant a symlink or a pid there
+fn runtime_dir() -> io::Result<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        if is_private(&dir) {
+            return Ok(dir);
+        }
+    }
+
+    let dir = env::temp_dir().join(format!("ultraman-{}", getuid()));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+    if !is_private(&dir) {
+        return Err(io::Error::other(format!(
+            "{} is not a directory only the user can access, processes are not tracked",
+            dir.display()
+        )));
+    }
+    Ok(dir)
+}
+
+// Not a symlink, owned by the user and closed to everyone else
+fn is_private(dir: &Path) -> bool {
+    match fs::symlink_metadata(dir) {
+        Ok(meta) => meta.is_dir() && meta.uid() == getuid().as_raw() && meta.mode() & 0o077 == 0,
+        Err(_) => false,
+    }
+}
+
+fn read_pgid_file(path: &Path) -> Option<String> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .ok()?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+// With the start time, so that another process that got the pid later is not taken for it
+// e.g.) 1234 567890
+fn entry(pid: i32) -> String {
+    format!("{} {}", pid, start_time(pid).unwrap_or(0))
+}
+
+fn parse_entry(line: &str) -> Option<(i32, u64)> {
+    let mut fields = line.split_whitespace();
+    let pid = fields.next()?.parse::<i32>().ok()?;
+    let start = fields.next()?.parse::<u64>().ok()?;
+    Some((pid, start))
+}
+
+// The recorded process is still running, and not another one with the same pid
+#[cfg(target_os = "linux")]
+fn is_recorded(pid: i32, start: u64) -> bool {
+    start_time(pid) == Some(start)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_recorded(pid: i32, _start: u64) -> bool {
+    signal::kill(Pid::from_raw(pid), None).is_ok()
+}
+
+fn cleanup_stale(path: &Path, opts: &DisplayOpts) {
+    let content = match read_pgid_file(path) {
+        Some(c) => c,
+        None => return,
+    };
+    let mut entries = content.lines().filter_map(parse_entry);
+
+    // Another ultraman is still running this Procfile, so its processes are not stale
+    match entries.next() {
+        Some((owner, start)) if is_recorded(owner, start) => return,
+        _ => (),
+    }
+
+    for (pgid, start) in entries {
+        // The leader is still there, but it is another process that got the pid
+        if start_time(pgid).is_some_and(|s| s != start) {
+            continue;
+        }
+        let pids = session_members(pgid, start);
+        if pids.is_empty() {
+            continue;
+        }
+
+        log::system(
+            opts,
+            &format!(
+                "killing stale process group {} from a previous run (pids: {})",
+                pgid,
+                pids.iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        );
+        for pid in pids {
+            let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+        }
+    }
+}
+
+// Processes left in the session, even if they moved to another process group.
+// Those that started before the leader can't be in its session, whatever their session id says.
+#[cfg(target_os = "linux")]
+fn session_members(sid: i32, since: u64) -> Vec<i32> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| {
+            let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(s) => s,
+                Err(_) => return false,
+            };
+            is_live_member(&stat, sid, since)
+        })
+        .collect()
+}
+
+// Without /proc the members can't be verified, so nothing is killed
+#[cfg(not(target_os = "linux"))]
+fn session_members(_sid: i32, _since: u64) -> Vec<i32> {
+    vec![]
+}
+
+// None for a zombie too, e.g. an ultraman that was killed and whose parent hasn't reaped it
+#[cfg(target_os = "linux")]
+fn start_time(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let fields = stat_fields(&stat);
+    if fields.first() == Some(&"Z") {
+        return None;
+    }
+    fields.get(19)?.parse::<u64>().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_time(_pid: i32) -> Option<u64> {
+    None
+}
+
+// e.g.) 1234 (sh) S 1000 1234 1234 0 -1 ... 567890 ...
+//                  state ppid pgrp session   start time (the 22nd field)
+// The fields after the command name, which may itself contain spaces and parentheses
+#[cfg(target_os = "linux")]
+fn stat_fields(stat: &str) -> Vec<&str> {
+    match stat.rfind(')') {
+        Some(i) => stat[i + 1..].split_whitespace().collect(),
+        None => vec![],
+    }
+}
+
+// Zombies are skipped because they are already dead and can't be killed
+#[cfg(target_os = "linux")]
+fn is_live_member(stat: &str, sid: i32, since: u64) -> bool {
+    let fields = stat_fields(stat);
+    match (fields.first(), fields.get(3), fields.get(19)) {
+        (Some(state), Some(session), Some(start)) => {
+            *state != "Z"
+                && session.parse::<i32>() == Ok(sid)
+                && start.parse::<u64>().is_ok_and(|s| s >= since)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_is_live_member() -> anyhow::Result<()> {
+        let stat = "1234 (npm run (dev)) S 1000 1200 1100 0 -1 4194560 \
+                    0 0 0 0 0 0 0 0 20 0 1 0 5000 0 0";
+        assert!(is_live_member(stat, 1100, 4000));
+        assert!(is_live_member(stat, 1100, 5000));
+        assert!(!is_live_member(stat, 1200, 4000));
+        // Older than the leader, so in a session that only has the same id
+        assert!(!is_live_member(stat, 1100, 6000));
+
+        let zombie = "1234 (sleep) Z 1 1100 1100 0 -1 4194560 0 0 0 0 0 0 0 0 20 0 1 0 5000 0 0";
+        assert!(!is_live_member(zombie, 1100, 4000));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_entry() -> anyhow::Result<()> {
+        let pid = getpid().as_raw();
+        let (recorded, start) = parse_entry(&entry(pid)).expect("failed parse entry");
+        assert_eq!(recorded, pid);
+        assert!(is_recorded(recorded, start));
+        assert!(!is_recorded(recorded, start + 1));
+        assert_eq!(parse_entry("1234"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pgid_file_path() -> anyhow::Result<()> {
+        let a = pgid_file_path(Path::new("/app/a/Procfile"))?;
+        let b = pgid_file_path(Path::new("/app/b/Procfile"))?;
+        assert_ne!(a, b);
+        assert_eq!(a, pgid_file_path(Path::new("/app/a/Procfile"))?);
+        assert!(is_private(a.parent().expect("failed get runtime dir")));
+
+        Ok(())
+    }
+}
@@ -31,13 +31,6 @@ impl Procfile {
             + 3
     }
 
-    pub fn process_len(&self) -> usize {
-        self.data
-            .values()
-            .map(|pe| pe.concurrency.get())
-            .fold(0, |sum, a| sum + a)
-    }
-
     pub fn find_by(&self, name: &str) -> &ProcfileEntry {
         let pe = self
             .data
@@ -63,18 +56,12 @@ impl Procfile {
             return ();
         }
 
-        let formation_data = self.parse_formation(formation);
-
-        // https://doc.rust-lang.org/std/collections/hash_map/struct.HashMap.html#examples-14
-        let formation_apps = formation_data.keys().clone().collect::<Vec<_>>();
-        let valid_formation = formation_apps
-            .iter()
-            .all(|key| self.data.contains_key(key.clone()));
-
-        if valid_formation == false {
+        if self.is_valid_formation(formation) == false {
             panic!("Do not support formation: {}", formation);
         }
 
+        let formation_data = self.parse_formation(formation);
+
         for (name, pe) in self.data.iter() {
             let pe_name = name;
             let concurrency = formation_data
@@ -85,6 +72,20 @@ impl Procfile {
         }
     }
 
+    // Every process named in the formation has to be in the Procfile, e.g. after it was reloaded
+    pub fn is_valid_formation(&self, formation: &str) -> bool {
+        if formation == DEFAULT_FORMATION || formation.starts_with("all=") {
+            return true;
+        }
+
+        // https://doc.rust-lang.org/std/collections/hash_map/struct.HashMap.html#examples-14
+        let formation_data = self.parse_formation(formation);
+        let formation_apps = formation_data.keys().clone().collect::<Vec<_>>();
+        formation_apps
+            .iter()
+            .all(|key| self.data.contains_key(key.clone()))
+    }
+
     fn parse_formation(&self, formation: &str) -> HashMap<String, usize> {
         let mut fm = formation.to_string();
         self.remove_whitespace(&mut fm);
@@ -112,7 +113,7 @@ pub fn read_procfile(filepath: PathBuf) -> Result<Procfile, Box<dyn std::error::
 
     let file = match File::open(filepath) {
         Ok(f) => f,
-        Err(why) => panic!("cloud't open {}: {}", display, why),
+        Err(why) => return Err(format!("cloud't open {}: {}", display, why).into()),
     };
 
     parse_procfile(&file)
@@ -126,7 +127,7 @@ fn parse_procfile(file: &File) -> Result<Procfile, Box<dyn std::error::Error>> {
     let buf_reader = BufReader::new(file);
 
     for line in buf_reader.lines() {
-        for cap in procfile_re.captures_iter(&line.unwrap()) {
+        for cap in procfile_re.captures_iter(&line?) {
             let name = (&cap[1]).to_string();
             pf.data.insert(
                 name,
@@ -190,15 +191,6 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn test_process_len() -> anyhow::Result<()> {
-        let pf = create_procfile();
-        let result = pf.process_len();
-        assert_eq!(result, 2);
-
-        Ok(())
-    }
-
     #[test]
     fn test_set_concurrency() -> anyhow::Result<()> {
         let formation = "app=2, web=3";
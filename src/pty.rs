@@ -0,0 +1,126 @@
+use crate::process::Process;
+
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::libc;
+use nix::pty::{openpty, Winsize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+// When ultraman itself is not in a terminal, e.g. with its output piped to a file
+const DEFAULT_SIZE: Winsize = Winsize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+// The size of the terminal ultraman runs in
+pub fn window_size() -> Option<Winsize> {
+    let mut size = DEFAULT_SIZE;
+    [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO]
+        .iter()
+        .find(|fd| unsafe { libc::ioctl(**fd, libc::TIOCGWINSZ, &mut size) } == 0)
+        .map(|_| size)
+}
+
+// Returns the master, which ultraman reads the output from, and the slave, which becomes
+// the stdin, stdout and stderr of the child
+pub fn open() -> io::Result<(File, File)> {
+    let size = window_size().unwrap_or(DEFAULT_SIZE);
+    let pty = openpty(&size, None).map_err(|_| io::Error::last_os_error())?;
+    let (master, slave) = unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+    // Otherwise other children would inherit them, and the master would not see the end of the output
+    for file in [&master, &slave].iter() {
+        fcntl(file.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .map_err(|_| io::Error::last_os_error())?;
+    }
+    Ok((master, slave))
+}
+
+// Give the child the slave as its stdio and controlling terminal, so that it colors its output
+// and flushes it line by line. Called after process_group::prepare, as only a session leader can
+// acquire a controlling terminal.
+pub fn prepare(command: &mut Command, slave: File) -> io::Result<()> {
+    command
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    unsafe {
+        command.pre_exec(|| {
+            if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+// Propagate the size of ultraman's terminal on SIGWINCH. The kernel then sends SIGWINCH to the
+// processes in each pseudo-terminal.
+pub fn resize_all(procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>) {
+    let size = match window_size() {
+        Some(s) => s,
+        None => return,
+    };
+    for proc in procs.lock().unwrap().iter() {
+        if let Some(master) = &proc.lock().unwrap().pty {
+            unsafe {
+                libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+            }
+        }
+    }
+}
+
+// Reading the master fails with EIO instead of returning 0 once the child and everything it
+// started have closed the slave, so that is the end of the output
+pub struct Reader(pub File);
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_prepare() -> anyhow::Result<()> {
+        let (master, slave) = open()?;
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("test -t 1 && echo tty; stty size");
+        // The child needs its own session to acquire a controlling terminal
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+        prepare(&mut command, slave)?;
+        let mut child = command.spawn()?;
+        drop(command);
+
+        let mut output = String::new();
+        Reader(master).read_to_string(&mut output)?;
+        child.wait()?;
+
+        let size = window_size().unwrap_or(DEFAULT_SIZE);
+        // ONLCR turns each \n into \r\n
+        assert_eq!(
+            output,
+            format!("tty\r\n{} {}\r\n", size.ws_row, size.ws_col)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use crate::config::ProcessConfig;
+use crate::env::Env;
+use crate::opt::DisplayOpts;
+use crate::output::Output;
+use crate::process::{self, Process};
+use crate::shell;
+use crate::signal;
+
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Probe {
+    // Connect to $PORT
+    Tcp,
+    // GET against localhost:$PORT, 2xx and 3xx are ready
+    Http {
+        #[serde(default = "default_http_path")]
+        path: String,
+    },
+    // Run a command with the process's env, exit status 0 is ready
+    Command {
+        command: String,
+    },
+    // Match against the process's own stdout and stderr
+    Output {
+        pattern: String,
+    },
+    // READY=1 sent to $NOTIFY_SOCKET
+    Notify,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ReadinessConfig {
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Seconds the process has to become ready before it is treated as failed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_http_path() -> String {
+    String::from("/")
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+impl Probe {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Probe::Tcp => "tcp",
+            Probe::Http { .. } => "http",
+            Probe::Command { .. } => "command",
+            Probe::Output { .. } => "output",
+            Probe::Notify => "notify",
+        }
+    }
+}
+
+// Fail before anything has started rather than in the output thread
+pub fn check(name: &str, config: &ProcessConfig) -> Result<(), String> {
+    match config.readiness.as_ref().map(|r| &r.probe) {
+        Some(Probe::Output { pattern }) if Regex::new(pattern).is_err() => Err(format!(
+            "invalid readiness pattern `{}` of {}",
+            pattern, name
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Checked by check before the process starts
+pub fn output_pattern(config: &ProcessConfig) -> Option<Regex> {
+    match config.readiness.as_ref().map(|r| &r.probe) {
+        Some(Probe::Output { pattern }) => Some(
+            Regex::new(pattern)
+                .unwrap_or_else(|_| panic!("Invalid readiness pattern: {}", pattern)),
+        ),
+        _ => None,
+    }
+}
+
+pub fn build_readiness_thread(
+    proc: Arc<Mutex<Process>>,
+    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    output: Arc<Output>,
+    opts: DisplayOpts,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("check readiness"))
+        .spawn(move || {
+            let (name, readiness, env, started_at) = {
+                let proc = proc.lock().unwrap();
+                (
+                    proc.name.clone(),
+                    proc.config.readiness.clone(),
+                    proc.env.clone(),
+                    proc.started_at,
+                )
+            };
+            let readiness = match readiness {
+                Some(r) => r,
+                None => return,
+            };
+            let timeout = Duration::from_secs(readiness.timeout);
+            let deadline = started_at + timeout;
+
+            loop {
+                // The process has already exited, so there is nothing to wait for
+                if !procs.lock().unwrap().iter().any(|p| Arc::ptr_eq(p, &proc)) {
+                    return;
+                }
+
+                if probe(&proc, &readiness.probe, &env, deadline) {
+                    {
+                        let mut proc = proc.lock().unwrap();
+                        proc.is_ready = true;
+                        proc.watchdog_at.get_or_insert(Instant::now());
+                    }
+                    output.log.output(
+                        "system",
+                        &format!(
+                            "{0:1$} ready after {2:.1}s",
+                            &name,
+                            opts.padding,
+                            started_at.elapsed().as_secs_f64()
+                        ),
+                    );
+                    return;
+                }
+
+                if started_at.elapsed() >= timeout {
+                    output.log.output(
+                        "system",
+                        &format!(
+                            "{0:1$} not ready after {2}s ({3} probe), failing",
+                            &name,
+                            opts.padding,
+                            readiness.timeout,
+                            readiness.probe.as_str()
+                        ),
+                    );
+                    signal::shutdown(procs, 1, opts);
+                    return;
+                }
+
+                sleep(PROBE_INTERVAL);
+            }
+        })
+        .expect("failed check readiness")
+}
+
+fn probe(proc: &Arc<Mutex<Process>>, probe: &Probe, env: &Env, deadline: Instant) -> bool {
+    let port = env.get("PORT").map(|p| p.as_str()).unwrap_or("5000");
+    match probe {
+        Probe::Tcp => connect(port).is_some(),
+        Probe::Http { path } => http_ok(port, path),
+        Probe::Command { command } => {
+            let config = proc.lock().unwrap().config.clone();
+            command_ok(command, env, &config, deadline)
+        }
+        // handle_output or the notify socket marks the process as ready
+        Probe::Output { .. } | Probe::Notify => proc.lock().unwrap().is_ready,
+    }
+}
+
+fn connect(port: &str) -> Option<TcpStream> {
+    let addrs = format!("localhost:{}", port).to_socket_addrs().ok()?;
+    for addr in addrs {
+        if let Ok(stream) = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            stream.set_read_timeout(Some(CONNECT_TIMEOUT)).ok()?;
+            return Some(stream);
+        }
+    }
+    None
+}
+
+fn http_ok(port: &str, path: &str) -> bool {
+    let mut stream = match connect(port) {
+        Some(s) => s,
+        None => return false,
+    };
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: localhost:{}\r\nConnection: close\r\n\r\n",
+        path, port
+    );
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+
+    let mut status_line = String::new();
+    if BufReader::new(stream).read_line(&mut status_line).is_err() {
+        return false;
+    }
+    is_success_status(&status_line)
+}
+
+// e.g.) HTTP/1.1 200 OK
+fn is_success_status(status_line: &str) -> bool {
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .map(|code| (200..400).contains(&code))
+        .unwrap_or(false)
+}
+
+// In the working directory of the process, e.g. for `test -f tmp/pids/server.pid`.
+// A command that hangs is killed at the deadline, with whatever it started, so that the timeout fires.
+fn command_ok(command: &str, env: &Env, config: &ProcessConfig, deadline: Instant) -> bool {
+    let mut command = shell::command(command, config.shell.as_deref(), false);
+    if let Some(cwd) = &config.cwd {
+        command.current_dir(cwd);
+    }
+    let child = command
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn();
+    let pid = match child {
+        Ok(child) => child.id(),
+        Err(_) => return false,
+    };
+    match process::wait_unclaimed(pid, Some(deadline)) {
+        Some(status) => matches!(status, WaitStatus::Exited(_, 0)),
+        None => {
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+            process::wait_unclaimed(pid, None);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_connect() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port().to_string();
+        assert!(connect(&port).is_some());
+
+        drop(listener);
+        assert!(connect(&port).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_ok() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port().to_string();
+        let server = thread::spawn(move || {
+            for status in &["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 512];
+                let _ = stream.read(&mut buf);
+                write!(stream, "HTTP/1.1 {}\r\n\r\n", status).unwrap();
+            }
+        });
+
+        assert!(!http_ok(&port, "/health"));
+        assert!(http_ok(&port, "/health"));
+        server.join().expect("failed join server");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> anyhow::Result<()> {
+        let config: ProcessConfig = serde_json::from_str(
+            r#"{ "readiness": { "type": "output", "pattern": "listening on \\d+" } }"#,
+        )?;
+        assert!(check("web", &config).is_ok());
+
+        let config: ProcessConfig = serde_json::from_str(
+            r#"{ "readiness": { "type": "output", "pattern": "listening (" } }"#,
+        )?;
+        assert_eq!(
+            check("web", &config),
+            Err(String::from(
+                "invalid readiness pattern `listening (` of web"
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_success_status() -> anyhow::Result<()> {
+        assert!(is_success_status("HTTP/1.1 200 OK\r\n"));
+        assert!(is_success_status("HTTP/1.0 302 Found\r\n"));
+        assert!(!is_success_status("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(!is_success_status(""));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use crate::app::{self, App};
+use crate::config::read_config;
+use crate::credentials;
+use crate::log;
+use crate::opt::DisplayOpts;
+use crate::output::Output;
+use crate::process::{self, Process};
+use crate::procfile::read_procfile;
+use crate::readiness;
+use crate::sandbox;
+use crate::signal;
+use crate::tmux;
+use crate::trigger;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+// What start loaded, so that it can be loaded again
+pub struct Source {
+    pub apps: Vec<App>,
+    pub timeout: u64,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub shell: Option<String>,
+    pub is_direct_exec: bool,
+    pub is_pty: bool,
+}
+
+// The mutex also keeps two reloads from running at the same time
+static SOURCE: OnceLock<Mutex<Source>> = OnceLock::new();
+
+pub fn init(source: Source) {
+    let _ = SOURCE.set(Mutex::new(source));
+}
+
+// Read the Procfile, .env and config of each app again and apply the difference to the running
+// processes: removed types are stopped, added ones started, and changed ones restarted
+pub fn reload(procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, opts: &DisplayOpts) {
+    let source = match SOURCE.get() {
+        Some(s) => s.lock().unwrap(),
+        None => return,
+    };
+    if signal::is_shutting_down() {
+        return;
+    }
+
+    let mut next_index = procs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| p.lock().unwrap().index + 1)
+        .max()
+        .unwrap_or(0);
+    let mut is_changed = false;
+    for app in source.apps.iter() {
+        is_changed |= reload_app(app, &source, procs, &mut next_index, opts);
+    }
+
+    if !is_changed {
+        log::system(opts, "reloaded, nothing changed");
+    }
+}
+
+// Returns whether anything of the app has changed. An app that fails to load keeps running as it is.
+fn reload_app(
+    app: &App,
+    source: &Source,
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    next_index: &mut usize,
+    opts: &DisplayOpts,
+) -> bool {
+    // An editor may have replaced the file and not written the new one yet
+    if !app.paths.procfile.exists() {
+        log::system(
+            opts,
+            &format!(
+                "failed reload, {} does not exist",
+                app.paths.procfile.display()
+            ),
+        );
+        return false;
+    }
+    let procfile = match read_procfile(app.paths.procfile.clone()) {
+        Ok(p) => p,
+        Err(e) => {
+            log::system(opts, &format!("failed reload, {}", e));
+            return false;
+        }
+    };
+    if !procfile.is_valid_formation(&app.formation) {
+        log::system(
+            opts,
+            &format!(
+                "failed reload, {} does not have every process in formation {}",
+                app.paths.procfile.display(),
+                app.formation
+            ),
+        );
+        return false;
+    }
+    procfile.set_concurrency(&app.formation);
+    let config = match read_config(app.paths.config.clone()) {
+        Ok(c) => c,
+        Err(e) => {
+            log::system(opts, &format!("failed reload, {}", e));
+            return false;
+        }
+    };
+
+    let process_config = |name: &str| {
+        let mut proc_config = config.find_by(name);
+        proc_config.stop_timeout.get_or_insert(source.timeout);
+        proc_config.user = proc_config.user.or_else(|| source.user.clone());
+        proc_config.group = proc_config.group.or_else(|| source.group.clone());
+        proc_config.shell = proc_config.shell.or_else(|| source.shell.clone());
+        proc_config.direct_exec.get_or_insert(source.is_direct_exec);
+        proc_config.pty.get_or_insert(source.is_pty);
+        proc_config.cwd = Some(app.paths.working_dir(proc_config.cwd.as_deref()));
+        if let Some(sandbox) = proc_config.sandbox.as_mut() {
+            sandbox.resolve(&app.paths);
+        }
+        proc_config
+    };
+    for name in procfile.data.keys() {
+        let proc_config = process_config(name);
+        let process_type = app.process_type(name);
+        let checked = app::check_working_dir(&process_type, &proc_config)
+            .and_then(|_| readiness::check(&process_type, &proc_config))
+            .and_then(|_| tmux::check(&process_type, &proc_config))
+            .and_then(|_| trigger::check(&process_type, &proc_config, &procfile))
+            .and_then(|_| credentials::lookup(&proc_config))
+            .and_then(|_| match &proc_config.sandbox {
+                Some(sandbox) => sandbox::check(&process_type, sandbox, opts),
+                None => Ok(()),
+            });
+        if let Err(e) = checked {
+            log::system(opts, &format!("failed reload, {}", e));
+            return false;
+        }
+    }
+
+    // By the name in the Procfile of the app
+    let mut running: HashMap<String, Vec<Arc<Mutex<Process>>>> = HashMap::new();
+    for proc in procs.lock().unwrap().iter() {
+        let process_type = proc.lock().unwrap().process_type().to_string();
+        if let Some(name) = app.procfile_name(&process_type) {
+            running
+                .entry(name.to_string())
+                .or_default()
+                .push(Arc::clone(proc));
+        }
+    }
+
+    // The env of every instance is read before anything changes, so that an .env saved halfway
+    // leaves the app as it is
+    let mut added_index = *next_index;
+    let mut planned = vec![];
+    for (name, pe) in procfile.data.iter() {
+        let proc_config = process_config(name);
+        // Scheduled process types are started by their schedule, which is read only at startup
+        if proc_config.schedule.is_some() {
+            continue;
+        }
+        let index = match running.get(name).and_then(|instances| instances.first()) {
+            Some(p) => p.lock().unwrap().index,
+            None => {
+                added_index += 1;
+                added_index - 1
+            }
+        };
+        let process_type = app.process_type(name);
+        let envs = (0..pe.concurrency.get())
+            .map(|n| app.env_for(&process_type, n, index, &proc_config))
+            .collect::<Result<Vec<_>, _>>();
+        match envs {
+            Ok(envs) => planned.push((name, pe, proc_config, index, envs)),
+            Err(e) => {
+                log::system(
+                    opts,
+                    &format!("failed reload, {}: {}", app.paths.env.display(), e),
+                );
+                return false;
+            }
+        }
+    }
+    *next_index = added_index;
+
+    let mut is_changed = false;
+    for (name, instances) in running.iter() {
+        if procfile.data.contains_key(name) {
+            continue;
+        }
+        is_changed = true;
+        log::system(
+            opts,
+            &format!(
+                "{} removed from the Procfile, stopping",
+                app.process_type(name)
+            ),
+        );
+        for proc in instances {
+            let proc = Arc::clone(proc);
+            let opts = opts.clone();
+            thread::spawn(move || process::remove(&proc, &opts));
+        }
+    }
+
+    for (name, pe, proc_config, index, envs) in planned {
+        let con = envs.len();
+        let process_type = app.process_type(name);
+        let instances = running.remove(name).unwrap_or_default();
+        if instances.is_empty() {
+            is_changed = true;
+            log::system(
+                opts,
+                &format!("{} added to the Procfile, starting", process_type),
+            );
+        }
+
+        let mut started = vec![false; con];
+        let mut restarts = vec![];
+        for proc in instances {
+            let mut locked = proc.lock().unwrap();
+            let n = locked.concurrency_index();
+            if n >= con {
+                is_changed = true;
+                log::system(opts, &format!("{} scaled down, stopping", locked.name));
+                let proc = Arc::clone(&proc);
+                let opts = opts.clone();
+                thread::spawn(move || process::remove(&proc, &opts));
+                continue;
+            }
+
+            started[n] = true;
+            let env = envs[n].clone();
+            locked.config = proc_config.clone();
+            if locked.command != pe.command || locked.env != env {
+                locked.command = pe.command.clone();
+                locked.env = env;
+                restarts.push(Arc::clone(&proc));
+            }
+        }
+
+        if !restarts.is_empty() {
+            is_changed = true;
+            log::system(opts, &format!("{} changed, restarting", process_type));
+            for proc in restarts {
+                let opts = opts.clone();
+                thread::spawn(move || process::restart(&proc, &opts));
+            }
+        }
+
+        let output = Arc::new(Output::new(index, opts.clone()));
+        for n in (0..con).filter(|n| !started[*n]) {
+            is_changed = true;
+            let proc = Process::new(
+                process_type.clone(),
+                pe.command.clone(),
+                envs[n].clone(),
+                n,
+                index,
+                Some(opts.clone()),
+                proc_config.clone(),
+            );
+            let proc = Arc::new(Mutex::new(proc));
+            process::on_start(&proc, procs, &output, opts);
+
+            let output = Arc::clone(&output);
+            let procs = Arc::clone(procs);
+            process::build_exec_and_output_thread(move || {
+                output.handle_output(&proc, &procs);
+            });
+        }
+    }
+
+    is_changed
+}
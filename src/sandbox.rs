@@ -0,0 +1,347 @@
+use crate::app::AppPaths;
+use crate::log;
+use crate::opt::DisplayOpts;
+
+use serde_derive::Deserialize;
+use std::path::PathBuf;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Paths the process may read and execute, relative to the root. When any path is given, everything else is denied
+    pub read_only: Vec<PathBuf>,
+    /// Paths the process may also write, create and remove files in, relative to the root
+    pub writable: Vec<PathBuf>,
+    /// false to deny TCP bind and connect, where the kernel supports it
+    pub network: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            read_only: vec![],
+            writable: vec![],
+            network: true,
+        }
+    }
+}
+
+impl SandboxConfig {
+    pub fn restricts_paths(&self) -> bool {
+        !self.read_only.is_empty() || !self.writable.is_empty()
+    }
+
+    // Relative paths are in the root of the app, like cwd
+    pub fn resolve(&mut self, paths: &AppPaths) {
+        for path in self.read_only.iter_mut().chain(self.writable.iter_mut()) {
+            *path = paths.working_dir(Some(path));
+        }
+    }
+}
+
+// Fail on a path that does not exist, as Landlock can't allow it, and warn about what this kernel can't restrict
+pub fn check(name: &str, config: &SandboxConfig, opts: &DisplayOpts) -> Result<(), String> {
+    if let Some(path) = config
+        .read_only
+        .iter()
+        .chain(config.writable.iter())
+        .find(|p| !p.exists())
+    {
+        return Err(format!(
+            "sandbox path {} of {} does not exist",
+            path.display(),
+            name
+        ));
+    }
+
+    let warn = |content: &str| log::system(opts, content);
+    match landlock_abi() {
+        None => warn(&format!(
+            "{} runs without a sandbox, as Landlock is not supported by this kernel",
+            name
+        )),
+        Some(abi) if abi < NET_ABI && !config.network => warn(&format!(
+            "{} can use the network, as restricting it with Landlock needs Linux 6.7 or later",
+            name
+        )),
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+const NET_ABI: i64 = 4;
+
+#[cfg(not(target_os = "linux"))]
+fn landlock_abi() -> Option<i64> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn prepare(_command: &mut std::process::Command, _config: &crate::config::ProcessConfig) {}
+
+// Landlock is Linux only. The libc crate in use predates it, so the ABI is written out here
+// from linux/landlock.h
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SandboxConfig;
+    use crate::config::ProcessConfig;
+
+    use nix::libc;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::os::unix::process::CommandExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    // The same on every architecture
+    const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+    const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+    const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+    const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+    const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+    // Rights that apply to a file, not only to a directory
+    const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_TRUNCATE
+        | ACCESS_FS_IOCTL_DEV;
+    const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+    const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+    const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+    // The first ABI that restricts the network
+    pub(super) const NET_ABI: i64 = 4;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    // e.g.) Some(4) on Linux 6.7, None when Landlock is not built in or disabled
+    pub(super) fn landlock_abi() -> Option<i64> {
+        let abi = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi > 0 {
+            Some(abi)
+        } else {
+            None
+        }
+    }
+
+    // Every filesystem right the ABI knows, so that none of them is left allowed
+    fn handled_access_fs(abi: i64) -> u64 {
+        match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        }
+    }
+
+    fn add_path(ruleset: &File, path: &Path, access: u64) -> io::Result<()> {
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let parent = unsafe { File::from_raw_fd(fd) };
+        let access = if path.is_dir() {
+            access
+        } else {
+            access & ACCESS_FILE
+        };
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: parent.as_raw_fd(),
+        };
+        let result = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_ADD_RULE,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // The ruleset is built here, and the child only has to restrict itself with it
+    fn build_ruleset(config: &SandboxConfig, abi: i64) -> io::Result<Option<File>> {
+        let handled_fs = if config.restricts_paths() {
+            handled_access_fs(abi)
+        } else {
+            0
+        };
+        let handled_net = if !config.network && abi >= NET_ABI {
+            ACCESS_NET_BIND_TCP | ACCESS_NET_CONNECT_TCP
+        } else {
+            0
+        };
+        if handled_fs == 0 && handled_net == 0 {
+            return Ok(None);
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled_fs,
+            handled_access_net: handled_net,
+        };
+        // Older kernels reject the fields they do not know
+        let size = if abi >= NET_ABI {
+            std::mem::size_of::<RulesetAttr>()
+        } else {
+            std::mem::size_of::<u64>()
+        };
+        let fd = unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const RulesetAttr,
+                size,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = unsafe { File::from_raw_fd(fd as i32) };
+
+        for path in config.read_only.iter() {
+            add_path(&ruleset, path, ACCESS_READ)?;
+        }
+        for path in config.writable.iter() {
+            add_path(&ruleset, path, handled_fs)?;
+        }
+        Ok(Some(ruleset))
+    }
+
+    // Restrict the child with no_new_privs and Landlock before exec.
+    // Without Landlock, only no_new_privs is set, and start has already warned about it.
+    pub fn prepare(command: &mut Command, config: &ProcessConfig) {
+        let sandbox = match &config.sandbox {
+            Some(s) => s,
+            None => return,
+        };
+        let ruleset = match landlock_abi() {
+            Some(abi) => build_ruleset(sandbox, abi),
+            None => Ok(None),
+        };
+
+        unsafe {
+            // The ruleset is closed in ultraman when the command is dropped after spawn
+            command.pre_exec(move || {
+                // A ruleset that could not be built fails the spawn, with no allocation in the child
+                let ruleset = match &ruleset {
+                    Ok(ruleset) => ruleset,
+                    Err(e) => {
+                        return Err(io::Error::from_raw_os_error(
+                            e.raw_os_error().unwrap_or(libc::EINVAL),
+                        ))
+                    }
+                };
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Some(ruleset) = &ruleset {
+                    if libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset.as_raw_fd(), 0) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_sandbox_config() -> anyhow::Result<()> {
+        let config: SandboxConfig = serde_json::from_str(r#"{ "network": false }"#)?;
+        assert!(!config.network);
+        assert!(!config.restricts_paths());
+
+        let config: SandboxConfig =
+            serde_json::from_str(r#"{ "read_only": ["/usr"], "writable": ["./tmp"] }"#)?;
+        assert!(config.network);
+        assert!(config.restricts_paths());
+        assert_eq!(config.writable, vec![PathBuf::from("./tmp")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> anyhow::Result<()> {
+        let mut config: SandboxConfig =
+            serde_json::from_str(r#"{ "read_only": ["/usr"], "writable": ["tmp"] }"#)?;
+        config.resolve(&AppPaths::resolve(
+            Some(PathBuf::from("/srv/app")),
+            None,
+            None,
+            None,
+        ));
+        assert_eq!(config.read_only, vec![PathBuf::from("/usr")]);
+        assert_eq!(config.writable, vec![PathBuf::from("/srv/app/tmp")]);
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_prepare() -> anyhow::Result<()> {
+        let writable = tempfile::tempdir()?;
+        let read_only = tempfile::tempdir()?;
+        let config: crate::config::ProcessConfig = serde_json::from_value(serde_json::json!({
+            "sandbox": { "read_only": ["/"], "writable": [writable.path()] }
+        }))?;
+
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg(format!(
+            "grep NoNewPrivs /proc/self/status; touch {}/a && echo ok; touch {}/a || echo denied",
+            writable.path().display(),
+            read_only.path().display()
+        ));
+        prepare(&mut command, &config);
+        let output = String::from_utf8(command.output()?.stdout)?;
+
+        assert!(output.starts_with("NoNewPrivs:\t1"));
+        // Only where the kernel supports Landlock
+        if landlock_abi().is_some() {
+            assert!(output.contains("ok\ndenied"));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::env;
+use std::process::Command;
+
+// Anything the shell would expand, redirect or chain
+const METACHARACTERS: &[char] = &[
+    '$', '`', '|', '&', ';', '<', '>', '(', ')', '*', '?', '[', ']', '{', '}', '~', '#', '!', '\n',
+];
+
+// Words that only mean something to the shell, so they can't be executed directly
+const BUILTINS: &[&str] = &[
+    ".", "alias", "case", "cd", "eval", "exec", "export", "for", "if", "read", "set", "source",
+    "trap", "ulimit", "umask", "unset", "until", "wait", "while",
+];
+
+// --shell or the shell of the process, then $SHELL, then /bin/sh as under cron or in a minimal container
+pub fn resolve(shell: Option<&str>) -> String {
+    shell
+        .map(String::from)
+        .or_else(|| env::var("SHELL").ok().filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| String::from("/bin/sh"))
+}
+
+// e.g.) Some(["bundle", "exec", "puma", "-C", "config/puma.rb"]) for `bundle exec puma -C config/puma.rb`,
+// None for `echo $HOME | tee log`
+pub fn split_simple(cmd: &str) -> Option<Vec<String>> {
+    if cmd.contains(METACHARACTERS) {
+        return None;
+    }
+    let words = shellwords::split(cmd).ok()?;
+    let program = words.first()?;
+    // e.g.) RAILS_ENV=production rails server
+    if program.contains('=') || BUILTINS.contains(&program.as_str()) {
+        return None;
+    }
+    Some(words)
+}
+
+// `$SHELL -c cmd`, or with direct exec, the program of a simple command without a shell in between,
+// so that it gets the signals itself
+pub fn command(cmd: &str, shell: Option<&str>, is_direct_exec: bool) -> Command {
+    if is_direct_exec {
+        if let Some(words) = split_simple(cmd) {
+            let mut command = Command::new(&words[0]);
+            command.args(&words[1..]);
+            return command;
+        }
+    }
+    let mut command = Command::new(resolve(shell));
+    command.arg("-c").arg(cmd);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_split_simple() -> anyhow::Result<()> {
+        assert_eq!(
+            split_simple("bundle exec puma -C 'config/puma dev.rb'"),
+            Some(vec![
+                String::from("bundle"),
+                String::from("exec"),
+                String::from("puma"),
+                String::from("-C"),
+                String::from("config/puma dev.rb"),
+            ])
+        );
+        assert_eq!(split_simple("echo $HOME"), None);
+        assert_eq!(split_simple("./bin/worker | tee log"), None);
+        assert_eq!(split_simple("sleep 1; echo done"), None);
+        assert_eq!(split_simple("RAILS_ENV=production rails s"), None);
+        assert_eq!(split_simple("exec node server.js"), None);
+        assert_eq!(split_simple("echo 'unclosed"), None);
+        assert_eq!(split_simple("  "), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command() -> anyhow::Result<()> {
+        let output = command("printf '%s,' a 'b c'", Some("/bin/sh"), true).output()?;
+        assert_eq!(String::from_utf8(output.stdout)?, "a,b c,");
+
+        let direct = command("printf x", None, true);
+        assert_eq!(direct.get_program(), "printf");
+
+        let shell = command("printf x", Some("/bin/dash"), false);
+        assert_eq!(shell.get_program(), "/bin/dash");
+        assert_eq!(shell.get_args().collect::<Vec<_>>(), vec!["-c", "printf x"]);
+
+        Ok(())
+    }
+}
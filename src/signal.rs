@@ -1,26 +1,57 @@
 use crate::log::{self, LogOpt};
 use crate::opt::DisplayOpts;
-use crate::process::{self, Process};
+use crate::process::{self, Process, ProcessState};
+use crate::process_group;
+use crate::pty;
+use crate::reload;
 
-use nix::sys::signal::{self, Signal};
-use nix::unistd::Pid;
-use signal_hook::{iterator::Signals, SIGALRM, SIGHUP, SIGINT, SIGTERM};
-use std::sync::{Arc, Mutex};
-use std::thread::{self, sleep, JoinHandle};
-use std::time::{Duration, Instant};
+use nix::libc::{SIGCONT, SIGTSTP, SIGTTIN, SIGTTOU};
+use nix::sys::signal::{raise, Signal};
+use serde::de::{self, Deserialize, Deserializer};
+use signal_hook::{
+    iterator::Signals, SIGALRM, SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2, SIGWINCH,
+};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 #[cfg(not(test))]
 use std::process::exit;
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum HupAction {
+    Forward,
+    Restart,
+    Reload,
+    Rolling,
+}
+
+pub fn hup_action(action: &str) -> HupAction {
+    if action == "forward" {
+        HupAction::Forward
+    } else if action == "restart" {
+        HupAction::Restart
+    } else if action == "reload" {
+        HupAction::Reload
+    } else if action == "rolling" {
+        HupAction::Rolling
+    } else {
+        panic!("Do not support SIGHUP action {}", action)
+    }
+}
+
 pub fn handle_signal_thread(
     procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
-    timeout: u64,
+    hup_action: HupAction,
+    is_init: bool,
     opts: DisplayOpts,
 ) -> JoinHandle<()> {
     let result = thread::Builder::new()
         .name(String::from("handling signal"))
         .spawn(move || {
-            trap_signal_at_multithred(procs, timeout, opts).expect("failed trap signals")
+            trap_signal_at_multithred(procs, hup_action, is_init, opts)
+                .expect("failed trap signals")
         })
         .expect("failed handle signals");
 
@@ -29,53 +60,151 @@ pub fn handle_signal_thread(
 
 fn trap_signal_at_multithred(
     procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
-    timeout: u64,
+    hup_action: HupAction,
+    is_init: bool,
     opts: DisplayOpts,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let signals = Signals::new(&[SIGALRM, SIGHUP, SIGINT, SIGTERM])?;
+    let signals = Signals::new([
+        SIGALRM, SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2, SIGTSTP, SIGCONT, SIGWINCH,
+    ])?;
+    // As PID 1, a signal without a handler is ignored instead of taking its default action,
+    // so like tini everything else that can be caught is passed on to the processes
+    if is_init {
+        for sig in &[SIGQUIT, SIGTTIN, SIGTTOU] {
+            signals.add_signal(*sig)?;
+        }
+    }
+    // Shutdown runs in its own thread, so that a second ^C can still be received.
+    // One may also have started elsewhere, e.g. when a process exited, and ^C forces that one.
+    let mut shutdown: Option<JoinHandle<()>> = None;
 
     for sig in signals.forever() {
+        let signal = Signal::try_from(sig)?;
         match sig {
-            SIGINT => {
-                // 2 is 「^C」 of 「^Csystem   | SIGINT received, starting shutdown」
-                log::output(
-                    "system",
-                    "SIGINT received, starting shutdown",
-                    None,
-                    &LogOpt {
-                        is_color: false,
-                        padding: opts.padding - 2,
-                        is_timestamp: opts.is_timestamp,
-                    },
-                );
+            SIGINT | SIGTERM => {
+                if shutdown.is_some() || is_shutting_down() {
+                    system_output(
+                        &format!(
+                            "{} received during shutdown, sending SIGKILL to all processes",
+                            signal.as_str()
+                        ),
+                        echo_padding(sig, &opts),
+                        &opts,
+                    );
+                    kill_children(procs.clone(), opts.clone());
+                    let code = SHUTDOWN_CODE.get().copied().unwrap_or(0);
+                    system_output(&format!("exit {}", code), opts.padding, &opts);
+                    #[cfg(not(test))]
+                    exit(code);
+                    #[cfg(test)]
+                    break;
+                }
 
-                log::output(
-                    "system",
-                    "sending SIGTERM to all processes",
-                    None,
-                    &LogOpt {
-                        is_color: false,
-                        padding: opts.padding,
-                        is_timestamp: opts.is_timestamp,
-                    },
+                system_output(
+                    &format!("{} received, starting shutdown", signal.as_str()),
+                    echo_padding(sig, &opts),
+                    &opts,
                 );
 
-                terminate_gracefully(procs, Signal::SIGTERM, 1, timeout, opts.clone());
-
-                log::output(
-                    "system",
-                    "exit 0",
-                    None,
-                    &LogOpt {
-                        is_color: false,
-                        padding: opts.padding,
-                        is_timestamp: opts.is_timestamp,
-                    },
-                );
-                #[cfg(not(test))]
-                exit(0);
+                let procs = procs.clone();
+                let opts = opts.clone();
+                shutdown = Some(thread::spawn(move || self::shutdown(procs, 0, opts)));
+
                 #[cfg(test)]
-                break;
+                {
+                    let _ = shutdown.take().unwrap().join();
+                    break;
+                }
+            }
+            SIGHUP if hup_action == HupAction::Restart && shutdown.is_none() => {
+                system_output(
+                    "SIGHUP received, restarting all processes",
+                    opts.padding,
+                    &opts,
+                );
+                for proc in procs.lock().unwrap().iter() {
+                    let proc = Arc::clone(proc);
+                    let opts = opts.clone();
+                    thread::spawn(move || process::restart(&proc, &opts));
+                }
+            }
+            SIGHUP if hup_action == HupAction::Reload && shutdown.is_none() => {
+                system_output(
+                    "SIGHUP received, reloading Procfile and .env",
+                    opts.padding,
+                    &opts,
+                );
+                let procs = procs.clone();
+                let opts = opts.clone();
+                thread::spawn(move || reload::reload(&procs, &opts));
+            }
+            SIGHUP if hup_action == HupAction::Rolling && shutdown.is_none() => {
+                system_output(
+                    "SIGHUP received, rolling restart of all processes",
+                    opts.padding,
+                    &opts,
+                );
+                // Each type is rolled on its own, so that one instance of every type is down at most
+                let mut process_types = procs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|p| p.lock().unwrap().process_type().to_string())
+                    .collect::<Vec<_>>();
+                // Instances of a type are not next to each other, e.g. web.1, worker.1, web.2
+                process_types.sort();
+                process_types.dedup();
+                for process_type in process_types {
+                    let procs = procs.clone();
+                    let opts = opts.clone();
+                    thread::spawn(move || process::rolling_restart(&procs, &process_type, &opts));
+                }
+            }
+            // The processes with --pty get their own SIGWINCH from the kernel
+            SIGWINCH if !is_init => pty::resize_all(&procs),
+            SIGWINCH => {
+                pty::resize_all(&procs);
+                system_output(
+                    "SIGWINCH received, forwarding to all processes",
+                    opts.padding,
+                    &opts,
+                );
+                forward_signal(&procs, signal);
+            }
+            SIGHUP | SIGUSR1 | SIGUSR2 | SIGQUIT | SIGTTIN | SIGTTOU => {
+                system_output(
+                    &format!("{} received, forwarding to all processes", signal.as_str()),
+                    opts.padding,
+                    &opts,
+                );
+                forward_signal(&procs, signal);
+            }
+            // There is no shell to resume a stopped init, so only the processes are suspended
+            SIGTSTP | SIGCONT if is_init => {
+                system_output(
+                    &format!("{} received, forwarding to all processes", signal.as_str()),
+                    opts.padding,
+                    &opts,
+                );
+                forward_signal(&procs, signal);
+            }
+            SIGTSTP => {
+                // The children are in their own sessions, so ^Z only reaches ultraman
+                system_output(
+                    "SIGTSTP received, suspending all processes",
+                    echo_padding(sig, &opts),
+                    &opts,
+                );
+                forward_signal(&procs, Signal::SIGSTOP);
+                raise(Signal::SIGSTOP)?;
+            }
+            SIGCONT => {
+                system_output(
+                    "SIGCONT received, resuming all processes",
+                    opts.padding,
+                    &opts,
+                );
+                forward_signal(&procs, Signal::SIGCONT);
             }
             _ => (),
         }
@@ -84,99 +213,117 @@ fn trap_signal_at_multithred(
     Ok(())
 }
 
-pub fn terminate_gracefully(
-    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
-    signal: Signal,
-    code: i32,
-    timeout: u64,
-    opts: DisplayOpts,
-) {
-    let procs2 = Arc::clone(&procs);
-    kill_children(procs, signal, code, opts.clone());
-
-    // Wait for all children to stop or until the time comes to kill them all
-    let start_time = Instant::now();
-    while start_time.elapsed() < Duration::from_secs(timeout) {
-        if procs2.lock().unwrap().len() == 0 {
-            return;
-        }
+// e.g.) "SIGQUIT" or "QUIT"
+pub fn parse_signal(name: &str) -> Result<Signal, String> {
+    let name = name.trim().to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    name.parse::<Signal>()
+        .map_err(|_| format!("Do not support signal {}", name))
+}
 
-        let procs3 = Arc::clone(&procs2);
-        process::check_for_child_termination(procs3, opts.clone());
+pub fn deserialize_signal<'de, D>(deserializer: D) -> Result<Option<Signal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    parse_signal(&name).map(Some).map_err(de::Error::custom)
+}
 
-        // Sleep for a moment and do not blow up if more signals are coming our way
-        sleep(Duration::from_millis(100));
+// 2 is 「^C」 of 「^Csystem   | SIGINT received, starting shutdown」 (and 「^Z」 for SIGTSTP)
+fn echo_padding(sig: i32, opts: &DisplayOpts) -> usize {
+    match sig {
+        SIGINT | SIGTSTP => opts.padding.saturating_sub(2),
+        _ => opts.padding,
     }
+}
 
-    // Ok, we have no other option than to kill all of our children
+fn system_output(content: &str, padding: usize, opts: &DisplayOpts) {
     log::output(
         "system",
-        "sending SIGKILL to all processes",
+        content,
         None,
         &LogOpt {
             is_color: false,
-            padding: opts.padding,
+            padding,
             is_timestamp: opts.is_timestamp,
         },
     );
+}
+
+// Unlike kill_children, a process that is already gone is not an error here
+fn forward_signal(procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, signal: Signal) {
+    for proc in procs.lock().unwrap().iter() {
+        let _ = process_group::kill(proc.lock().unwrap().child.id(), signal);
+    }
+}
 
-    kill_children(procs2, Signal::SIGKILL, 0, opts);
+// The exit code of whatever started the shutdown. Set once, so only the first one is acted on.
+static SHUTDOWN_CODE: OnceLock<i32> = OnceLock::new();
+
+pub fn is_shutting_down() -> bool {
+    SHUTDOWN_CODE.get().is_some()
 }
 
-pub fn kill_children(
-    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
-    signal: Signal,
-    _code: i32,
-    opts: DisplayOpts,
-) {
+// Stop everything and exit. Does nothing if a shutdown has already started elsewhere.
+pub fn shutdown(procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, code: i32, opts: DisplayOpts) {
+    if SHUTDOWN_CODE.set(code).is_err() {
+        return;
+    }
+
+    system_output("stopping all processes", opts.padding, &opts);
+    terminate_gracefully(procs, opts.clone());
+
+    system_output(&format!("exit {}", code), opts.padding, &opts);
+    // https://www.reddit.com/r/rust/comments/emz456/testing_whether_functions_exit/
+    #[cfg(not(test))]
+    exit(code);
+    #[cfg(test)]
+    panic!("exit {}", code);
+}
+
+// Each process gets its own stop signal, and SIGKILL once its own stop timeout has passed
+pub fn terminate_gracefully(procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, opts: DisplayOpts) {
     for proc in procs.lock().unwrap().iter() {
-        let proc = proc.lock().unwrap();
-        let child = &proc.child;
-
-        log::output(
-            "system",
-            &format!(
-                "sending {3} for {0:1$} at pid {2}",
-                &proc.name,
-                opts.padding,
-                &child.id(),
-                Signal::as_str(signal),
-            ),
-            None,
-            &LogOpt {
-                is_color: false,
-                padding: opts.padding,
-                is_timestamp: opts.is_timestamp,
-            },
-        );
+        let mut proc = proc.lock().unwrap();
+        // Shutting down takes precedence over a restart in progress
+        proc.is_restarting = false;
+        proc.stop(&opts);
+    }
 
-        if let Err(e) = signal::kill(Pid::from_raw(child.id() as i32), signal) {
-            log::error(
-                "system",
-                &e,
-                true,
-                &LogOpt {
-                    is_color: false,
-                    padding: opts.padding,
-                    is_timestamp: opts.is_timestamp,
-                },
-            );
-            log::output(
-                "system",
-                &format!("exit {}", _code),
-                None,
-                &LogOpt {
-                    is_color: false,
-                    padding: opts.padding,
-                    is_timestamp: opts.is_timestamp,
-                },
-            );
-            // https://www.reddit.com/r/rust/comments/emz456/testing_whether_functions_exit/
-            #[cfg(not(test))]
-            exit(_code);
-            #[cfg(test)]
-            panic!("exit {}", _code);
+    // Wait for all children to stop, killing the ones that take too long
+    loop {
+        let reaped = process::reaped_count();
+        // Usually the check thread has reaped them already, this covers the rest
+        process::check_for_child_termination(Arc::clone(&procs), opts.clone());
+
+        let mut next_deadline = None;
+        for proc in procs.lock().unwrap().iter() {
+            let mut proc = proc.lock().unwrap();
+            if proc.is_past_deadline() {
+                proc.kill(&opts);
+            }
+            if let ProcessState::Stopping { deadline } = proc.state {
+                next_deadline = Some(next_deadline.map_or(deadline, |d: Instant| d.min(deadline)));
+            }
         }
+
+        if procs.lock().unwrap().is_empty() {
+            return;
+        }
+        process::wait_for_reap(reaped, next_deadline);
+    }
+}
+
+// A process that is already gone is skipped, the others still get the signal
+pub fn kill_children(procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>, opts: DisplayOpts) {
+    for proc in procs.lock().unwrap().iter() {
+        let mut proc = proc.lock().unwrap();
+        proc.is_restarting = false;
+        proc.kill(&opts);
     }
 }
 
@@ -184,17 +331,43 @@ pub fn kill_children(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ProcessConfig;
+    use crate::env::Env;
     use libc;
     use signal_hook::SIGINT;
     use std::process::Command;
     use std::thread::sleep;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     // https://github.com/vorner/signal-hook/blob/master/tests/iterator.rs
     fn send_sigint() {
         unsafe { libc::raise(SIGINT) };
     }
 
+    #[test]
+    fn test_hup_action() {
+        assert_eq!(hup_action("forward"), HupAction::Forward);
+        assert_eq!(hup_action("restart"), HupAction::Restart);
+        assert_eq!(hup_action("reload"), HupAction::Reload);
+        assert_eq!(hup_action("rolling"), HupAction::Rolling);
+    }
+
+    #[test]
+    #[should_panic(expected = "Do not support SIGHUP action stop")]
+    fn test_hup_action_when_panic() {
+        hup_action("stop");
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGQUIT"), Ok(Signal::SIGQUIT));
+        assert_eq!(parse_signal("int"), Ok(Signal::SIGINT));
+        assert_eq!(
+            parse_signal("SIGNOPE"),
+            Err(String::from("Do not support signal SIGNOPE"))
+        );
+    }
+
     #[test]
     #[ignore]
     fn test_trap_signal_at_multithred() {
@@ -207,6 +380,16 @@ mod tests {
                     .spawn()
                     .expect("failed execute test-app-1"),
                 opts: None,
+                command: String::new(),
+                env: Env::new(),
+                config: ProcessConfig::default(),
+                started_at: Instant::now(),
+                is_ready: false,
+                is_restarting: false,
+                is_removing: false,
+                watchdog_at: None,
+                pty: None,
+                state: ProcessState::Running,
             })),
             Arc::new(Mutex::new(Process {
                 index: 1,
@@ -216,6 +399,16 @@ mod tests {
                     .spawn()
                     .expect("failed execute test-app-2"),
                 opts: None,
+                command: String::new(),
+                env: Env::new(),
+                config: ProcessConfig::default(),
+                started_at: Instant::now(),
+                is_ready: false,
+                is_restarting: false,
+                is_removing: false,
+                watchdog_at: None,
+                pty: None,
+                state: ProcessState::Running,
             })),
         ]));
 
@@ -223,7 +416,8 @@ mod tests {
         let thread_trap_signal = thread::spawn(move || {
             trap_signal_at_multithred(
                 procs2,
-                5,
+                HupAction::Forward,
+                false,
                 DisplayOpts {
                     padding: 10,
                     is_timestamp: true,
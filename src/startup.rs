@@ -0,0 +1,114 @@
+use nix::sys::signal::{raise, Signal};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+
+// While processes are still being started, an empty process list does not mean that all have terminated
+static IS_STARTING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_starting() -> bool {
+    IS_STARTING.load(Ordering::SeqCst)
+}
+
+// Limits how many instances are starting at the same time, in total and per process type.
+// An instance is starting until it is ready if it has a readiness probe, and until it is spawned otherwise.
+pub struct Startup {
+    max_parallel: Option<usize>,
+    starting: Mutex<HashMap<String, usize>>,
+    released: Condvar,
+}
+
+// A limit of 0 would never let anything start
+pub fn check(name: &str, max_parallel: Option<usize>) -> Result<(), String> {
+    match max_parallel {
+        Some(0) => Err(format!(
+            "max parallel starts of {} must be at least 1",
+            name
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Call finish once every process has been started
+pub fn begin(max_parallel: Option<usize>) -> Startup {
+    IS_STARTING.store(true, Ordering::SeqCst);
+    Startup::new(max_parallel)
+}
+
+impl Startup {
+    fn new(max_parallel: Option<usize>) -> Self {
+        Startup {
+            max_parallel,
+            starting: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    // Block until one more instance of the process type may start
+    pub fn acquire(&self, process_type: &str, max_parallel: Option<usize>) {
+        let mut starting = self.starting.lock().unwrap();
+        loop {
+            let total = starting.values().sum::<usize>();
+            let of_type = starting.get(process_type).copied().unwrap_or(0);
+            let is_full = self.max_parallel.is_some_and(|max| total >= max)
+                || max_parallel.is_some_and(|max| of_type >= max);
+            if !is_full {
+                break;
+            }
+            starting = self.released.wait(starting).unwrap();
+        }
+        *starting.entry(process_type.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn release(&self, process_type: &str) {
+        let mut starting = self.starting.lock().unwrap();
+        if let Some(count) = starting.get_mut(process_type) {
+            *count = count.saturating_sub(1);
+        }
+        self.released.notify_all();
+    }
+
+    pub fn finish(&self) {
+        IS_STARTING.store(false, Ordering::SeqCst);
+        // Every process may have terminated already, so let the reaper check the list again
+        let _ = raise(Signal::SIGCHLD);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_startup_limits_parallel_starts() -> anyhow::Result<()> {
+        let startup = Arc::new(Startup::new(Some(2)));
+        startup.acquire("web", Some(1));
+        startup.acquire("worker", None);
+
+        let waiting = {
+            let startup = Arc::clone(&startup);
+            thread::spawn(move || startup.acquire("worker", None))
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+
+        startup.release("web");
+        waiting.join().unwrap();
+        assert_eq!(*startup.starting.lock().unwrap().get("worker").unwrap(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_rejects_zero() -> anyhow::Result<()> {
+        assert!(check("web", Some(0)).is_err());
+        assert!(check("web", Some(1)).is_ok());
+        assert!(check("web", None).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use crate::app::App;
+use crate::env::read_env;
+use crate::log;
+use crate::opt::DisplayOpts;
+use crate::process::{self, Process};
+use crate::shell;
+use crate::signal;
+
+use nix::libc;
+use nix::sys::signal::{killpg, pthread_sigmask, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::{getpgrp, getpid, getppid, isatty, tcgetpgrp, tcsetpgrp, Pid};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How often the command is checked for a shutdown that started elsewhere
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// While the command runs, no running process does not mean that ultraman is done, as its exit code
+// is that of the command. Not cleared when the command exits, since the shutdown with its code follows.
+static IS_RUNNING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_running() -> bool {
+    IS_RUNNING.load(Ordering::SeqCst)
+}
+
+// With --then, the processes are only there for a command, e.g. the integration tests in CI.
+// The command runs once every process is running, or ready if it has a readiness probe, and its
+// exit code becomes that of ultraman after the processes have stopped.
+pub fn build_then_thread(
+    command: String,
+    app: App,
+    shell: Option<String>,
+    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    opts: DisplayOpts,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("run then command"))
+        .spawn(move || {
+            let started = procs.lock().unwrap().clone();
+            for proc in started.iter() {
+                process::wait_until_ready(&procs, proc);
+            }
+            // A process has exited or failed its readiness probe, and its code is the exit code
+            if signal::is_shutting_down() {
+                return;
+            }
+
+            log::system(
+                &opts,
+                &format!("all processes are up, running `{}`", command),
+            );
+            let started_at = Instant::now();
+            IS_RUNNING.store(true, Ordering::SeqCst);
+            let code = match spawn(&command, &app, shell.as_deref()) {
+                Ok(pid) => {
+                    let is_foreground = give_terminal(pid);
+                    let status = wait(pid);
+                    if is_foreground {
+                        take_terminal_back();
+                    }
+                    match status {
+                        WaitStatus::Exited(_, code) => code,
+                        WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+                        _ => 1,
+                    }
+                }
+                Err(e) => {
+                    log::system(&opts, &format!("failed run `{}`: {}", command, e));
+                    1
+                }
+            };
+            if signal::is_shutting_down() {
+                return;
+            }
+            log::system(
+                &opts,
+                &format!(
+                    "`{}` exited with code {} in {:.1}s",
+                    command,
+                    code,
+                    started_at.elapsed().as_secs_f64()
+                ),
+            );
+            signal::shutdown(procs, code, opts);
+        })
+        .expect("failed run then command")
+}
+
+// In the root with the .env of the app, and in a process group of its own, so that stopping it
+// stops everything it started too
+fn spawn(command: &str, app: &App, shell: Option<&str>) -> io::Result<u32> {
+    // e.g.) .env was saved halfway after the processes started
+    let env = read_env(app.paths.env.clone()).map_err(|e| io::Error::other(e.to_string()))?;
+    let parent = getpid();
+    let mut command = shell::command(command, shell, false);
+    command
+        .envs(env)
+        // The processes report to ultraman, the command does not
+        .env_remove("NOTIFY_SOCKET")
+        .current_dir(&app.paths.root)
+        .process_group(0);
+    // This thread waits for the command, so it lives as long as the command does
+    #[cfg(target_os = "linux")]
+    unsafe {
+        command.pre_exec(move || {
+            nix::libc::prctl(nix::libc::PR_SET_PDEATHSIG, nix::libc::SIGKILL);
+            if getppid() != parent {
+                nix::libc::_exit(1);
+            }
+            Ok(())
+        });
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = parent;
+    Ok(command.spawn()?.id())
+}
+
+// Like a shell runs a job in the foreground, so that what is typed and ^C go to the command.
+// Returns false when ultraman does not have a terminal to give, e.g. in CI.
+fn give_terminal(pid: u32) -> bool {
+    let is_foreground = isatty(libc::STDIN_FILENO).unwrap_or(false)
+        && tcgetpgrp(libc::STDIN_FILENO).ok() == Some(getpgrp());
+    if !is_foreground {
+        return false;
+    }
+    let pgid = Pid::from_raw(pid as i32);
+    let _ = tcsetpgrp(libc::STDIN_FILENO, pgid);
+    // It may have read the terminal before it got it, and been stopped for that
+    let _ = killpg(pgid, Signal::SIGCONT);
+    true
+}
+
+// ultraman is in the background now, and taking the terminal from there raises SIGTTOU unless
+// it is blocked
+fn take_terminal_back() {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTTOU);
+    let _ = pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), None);
+    let _ = tcsetpgrp(libc::STDIN_FILENO, getpgrp());
+}
+
+// The thread that reaps every child gets the status, so it is taken from there
+fn wait(pid: u32) -> WaitStatus {
+    let mut is_stopping = false;
+    loop {
+        let reaped = process::reaped_count();
+        if let Some(status) = process::take_unclaimed(pid) {
+            return status;
+        }
+        // A process exited first, so the command stops together with the others
+        if signal::is_shutting_down() && !is_stopping {
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGTERM);
+            is_stopping = true;
+        }
+        process::wait_for_reap(reaped, Some(Instant::now() + POLL_INTERVAL));
+    }
+}
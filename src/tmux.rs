@@ -0,0 +1,314 @@
+use crate::config::ProcessConfig;
+use crate::log;
+use crate::opt::DisplayOpts;
+use crate::process::{self, Process, ProcessState};
+use crate::pty;
+use crate::readiness;
+use crate::signal;
+
+use nix::libc;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::getpid;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const SESSION_NAME: &str = "ultraman";
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// With --tmux, each process writes to and reads from the terminal of its own tmux window.
+// The processes are still children of ultraman, so stopping and restarting them works as without tmux.
+struct Session {
+    // e.g.) ultraman-1234 for `tmux -L ultraman-1234`, a server of its own
+    socket: String,
+    // The window id of each instance, e.g.) web.1 => @3
+    windows: Mutex<HashMap<String, String>>,
+}
+
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+impl Session {
+    fn new(socket: String) -> Result<Session, String> {
+        let mut command = Command::new("tmux");
+        command.arg("-V");
+        if run(command).is_err() {
+            return Err(String::from("--tmux needs tmux, which is not in PATH"));
+        }
+        Ok(Session {
+            socket,
+            windows: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn tmux(&self, args: &[&str]) -> io::Result<String> {
+        let mut command = Command::new("tmux");
+        command.arg("-L").arg(&self.socket).args(args);
+        run(command)
+    }
+
+    // The terminal of the window of the instance. The window is kept across restarts, and opened
+    // again if it was closed.
+    fn open_pane(&self, name: &str) -> io::Result<File> {
+        let mut windows = self.windows.lock().unwrap();
+
+        let tty = match windows.get(name) {
+            Some(id) => self
+                .tmux(&["display-message", "-p", "-t", id, "#{pane_tty}"])
+                .ok(),
+            None => None,
+        };
+        let tty = match tty {
+            Some(tty) => tty,
+            None => {
+                let placeholder = placeholder();
+                let format = "#{window_id} #{pane_tty}";
+                let is_started = self
+                    .tmux(&["display-message", "-p", "-t", SESSION_NAME, "#{session_id}"])
+                    .is_ok();
+                let created = if is_started {
+                    let target = format!("{}:", SESSION_NAME);
+                    self.tmux(&[
+                        "new-window",
+                        "-d",
+                        "-t",
+                        &target,
+                        "-n",
+                        name,
+                        "-P",
+                        "-F",
+                        format,
+                        "sh",
+                        "-c",
+                        &placeholder,
+                    ])?
+                } else {
+                    // The size of the terminal ultraman runs in until a client attaches
+                    let size = pty::window_size();
+                    let cols = size.map_or(80, |s| s.ws_col).to_string();
+                    let rows = size.map_or(24, |s| s.ws_row).to_string();
+                    self.tmux(&[
+                        "new-session",
+                        "-d",
+                        "-s",
+                        SESSION_NAME,
+                        "-n",
+                        name,
+                        "-x",
+                        &cols,
+                        "-y",
+                        &rows,
+                        "-P",
+                        "-F",
+                        format,
+                        "sh",
+                        "-c",
+                        &placeholder,
+                    ])?
+                };
+                // e.g.) @3 /dev/pts/5
+                let (id, tty) = created
+                    .split_once(' ')
+                    .ok_or_else(|| io::Error::other("unexpected output of tmux"))?;
+                windows.insert(name.to_string(), id.to_string());
+                tty.to_string()
+            }
+        };
+
+        // Not as the controlling terminal of ultraman, which may have none
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(tty)
+    }
+}
+
+// The trimmed stdout of a tmux command
+fn run(mut command: Command) -> io::Result<String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut stdout)?;
+    child.stderr.take().unwrap().read_to_string(&mut stderr)?;
+    let is_success = match child.wait() {
+        Ok(status) => status.success(),
+        // Once it has started, the thread that reaps every child may get the status first
+        Err(e) if e.raw_os_error() == Some(libc::ECHILD) => matches!(
+            process::wait_unclaimed(child.id(), None),
+            Some(WaitStatus::Exited(_, 0))
+        ),
+        Err(e) => return Err(e),
+    };
+    if !is_success {
+        // e.g.) tmux failed: can't find session: ultraman
+        return Err(io::Error::other(format!("tmux failed: {}", stderr.trim())));
+    }
+    Ok(stdout.trim().to_string())
+}
+
+// Fail before anything has started when there is no tmux to run the processes in
+pub fn init() -> Result<(), String> {
+    let session = Session::new(format!("ultraman-{}", getpid()))?;
+    let _ = SESSION.set(session);
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    SESSION.get().is_some()
+}
+
+// e.g.) tmux -L ultraman-1234 attach -t ultraman
+pub fn attach_command() -> String {
+    let socket = SESSION.get().map_or("", |s| s.socket.as_str());
+    format!("tmux -L {} attach -t {}", socket, SESSION_NAME)
+}
+
+// The output of a process is only in its window, so it can't be matched
+pub fn check(name: &str, config: &ProcessConfig) -> Result<(), String> {
+    if is_enabled() && readiness::output_pattern(config).is_some() {
+        return Err(format!(
+            "the output readiness probe of {} does not work with --tmux",
+            name
+        ));
+    }
+    if is_enabled() && !config.triggers.is_empty() {
+        return Err(format!("the triggers of {} do not work with --tmux", name));
+    }
+    Ok(())
+}
+
+// What runs in each window while the process writes to its terminal. ^C in the window is sent to
+// the process, and the window closes once ultraman has exited, however it did.
+fn placeholder() -> String {
+    format!(
+        "trap 'kill -INT -$(tmux show-options -wqv @ultraman_pgid) 2>/dev/null' INT; \
+         trap '' QUIT TSTP; \
+         while kill -0 {} 2>/dev/null; do sleep 1; done",
+        getpid()
+    )
+}
+
+pub fn open_pane(name: &str) -> io::Result<File> {
+    SESSION
+        .get()
+        .ok_or_else(|| io::Error::other("tmux is not enabled"))?
+        .open_pane(name)
+}
+
+// For ^C in the window. The child leads its own process group, so its pid is the group id.
+pub fn record(name: &str, pid: u32) {
+    let session = match SESSION.get() {
+        Some(s) => s,
+        None => return,
+    };
+    if let Some(id) = session.windows.lock().unwrap().get(name) {
+        let pid = pid.to_string();
+        let _ = session.tmux(&["set-option", "-w", "-t", id, "@ultraman_pgid", &pid]);
+    }
+}
+
+// Closing the window of a process stops it, and ending the session stops everything
+pub fn build_watch_thread(
+    procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+    opts: DisplayOpts,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("watch tmux"))
+        .spawn(move || {
+            log::system(
+                &opts,
+                &format!("processes run in tmux, attach with `{}`", attach_command()),
+            );
+
+            let session = SESSION.get().expect("failed get tmux session");
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                if signal::is_shutting_down() {
+                    return;
+                }
+
+                let ids =
+                    match session.tmux(&["list-windows", "-t", SESSION_NAME, "-F", "#{window_id}"])
+                    {
+                        Ok(ids) => ids,
+                        // The session is created with the first window, e.g. after --start-delay
+                        Err(_) if session.windows.lock().unwrap().is_empty() => continue,
+                        Err(_) => {
+                            log::system(&opts, "tmux session has ended");
+                            signal::shutdown(procs, 0, opts);
+                            return;
+                        }
+                    };
+                let closed = {
+                    let mut windows = session.windows.lock().unwrap();
+                    let closed = windows
+                        .iter()
+                        .filter(|(_, id)| !ids.lines().any(|l| l == id.as_str()))
+                        .map(|(name, _)| name.clone())
+                        .collect::<Vec<_>>();
+                    for name in closed.iter() {
+                        windows.remove(name);
+                    }
+                    closed
+                };
+
+                for name in closed {
+                    let proc = procs
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|p| p.lock().unwrap().name == name)
+                        .cloned();
+                    if let Some(proc) = proc {
+                        let mut proc = proc.lock().unwrap();
+                        if proc.state == ProcessState::Running {
+                            log::system(&opts, &format!("{} window closed, stopping", name));
+                            proc.stop(&opts);
+                        }
+                    }
+                }
+            }
+        })
+        .expect("failed watch tmux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+    use std::io::Write;
+
+    #[test]
+    fn test_open_pane() -> anyhow::Result<()> {
+        // A server of its own, and only where tmux is installed
+        let session = match Session::new(format!("ultraman-test-{}", getpid())) {
+            Ok(s) => s,
+            Err(_) => return Ok(()),
+        };
+
+        let mut pane = session.open_pane("web.1")?;
+        writeln!(pane, "hello from web.1")?;
+        // The same window again
+        session.open_pane("web.1")?;
+        let windows =
+            session.tmux(&["list-windows", "-t", SESSION_NAME, "-F", "#{window_name}"])?;
+        assert_eq!(windows, "web.1");
+
+        thread::sleep(Duration::from_millis(200));
+        let id = session.windows.lock().unwrap()["web.1"].clone();
+        let content = session.tmux(&["capture-pane", "-p", "-t", &id])?;
+        let _ = session.tmux(&["kill-server"]);
+        assert!(content.contains("hello from web.1"));
+
+        Ok(())
+    }
+}
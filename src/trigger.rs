@@ -0,0 +1,297 @@
+use crate::config::ProcessConfig;
+use crate::log::{self, LogOpt};
+use crate::opt::DisplayOpts;
+use crate::process::{self, Process};
+use crate::procfile::Procfile;
+use crate::shell;
+use crate::signal;
+
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::unix::io::FromRawFd;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    // The instance that printed the line, or every instance of another process type of its app
+    Restart {
+        #[serde(default)]
+        process: Option<String>,
+    },
+    // A shell command with the env of the process and the line in $MATCHED_LINE
+    Run {
+        command: String,
+    },
+    // Stop everything and exit with the code
+    Shutdown {
+        #[serde(default = "default_code")]
+        code: i32,
+    },
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TriggerConfig {
+    /// Regex matched against each line of stdout and stderr
+    pub pattern: String,
+    #[serde(flatten)]
+    pub action: Action,
+    /// Seconds after firing during which the trigger ignores further matches
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+}
+
+fn default_code() -> i32 {
+    1
+}
+
+fn default_cooldown() -> u64 {
+    30
+}
+
+pub struct Trigger {
+    re: Regex,
+    config: TriggerConfig,
+}
+
+// When each trigger of each instance last fired. Kept across restarts, so that a process that
+// prints the line again while starting up is not restarted over and over.
+static FIRED: Mutex<Vec<(String, usize, Instant)>> = Mutex::new(vec![]);
+
+// Fail before anything has started rather than on the first line
+pub fn check(name: &str, config: &ProcessConfig, procfile: &Procfile) -> Result<(), String> {
+    compile(name, config)?;
+    for trigger in config.triggers.iter() {
+        if let Action::Restart {
+            process: Some(process),
+        } = &trigger.action
+        {
+            if !procfile.data.contains_key(process) {
+                return Err(format!(
+                    "a trigger of {} restarts {}, which is not in the Procfile",
+                    name, process
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn compile(name: &str, config: &ProcessConfig) -> Result<Vec<Trigger>, String> {
+    config
+        .triggers
+        .iter()
+        .map(|t| match Regex::new(&t.pattern) {
+            Ok(re) => Ok(Trigger {
+                re,
+                config: t.clone(),
+            }),
+            Err(_) => Err(format!(
+                "invalid trigger pattern `{}` of {}",
+                t.pattern, name
+            )),
+        })
+        .collect()
+}
+
+// Take the action of each trigger that matches the line and is not cooling down
+pub fn handle(
+    triggers: &[Trigger],
+    line: &str,
+    proc: &Arc<Mutex<Process>>,
+    procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+) {
+    for (i, trigger) in triggers.iter().enumerate() {
+        if !trigger.re.is_match(line) {
+            continue;
+        }
+        let (name, opts) = {
+            let proc = proc.lock().unwrap();
+            (proc.name.clone(), proc.opts.clone().unwrap_or_default())
+        };
+        let cooldown = Duration::from_secs(trigger.config.cooldown);
+        if !is_due(
+            &mut FIRED.lock().unwrap(),
+            &name,
+            i,
+            Instant::now(),
+            cooldown,
+        ) {
+            continue;
+        }
+
+        let matched = format!("{} matched `{}`", name, trigger.config.pattern);
+
+        match &trigger.config.action {
+            Action::Restart { process: None } => {
+                log::system(&opts, &format!("{}, restarting", matched));
+                let proc = Arc::clone(proc);
+                thread::spawn(move || process::restart(&proc, &opts));
+            }
+            Action::Restart {
+                process: Some(process),
+            } => {
+                let process_type = target_type(proc.lock().unwrap().process_type(), process);
+                log::system(&opts, &format!("{}, restarting {}", matched, process_type));
+                let instances = procs
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|p| p.lock().unwrap().process_type() == process_type)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for proc in instances {
+                    let opts = opts.clone();
+                    thread::spawn(move || process::restart(&proc, &opts));
+                }
+            }
+            Action::Run { command } => {
+                log::system(&opts, &format!("{}, running `{}`", matched, command));
+                if let Err(e) = run(command, line, proc, &opts) {
+                    log::system(&opts, &format!("failed run `{}`: {}", command, e));
+                }
+            }
+            Action::Shutdown { code } => {
+                log::system(&opts, &format!("{}, shutting down", matched));
+                let procs = Arc::clone(procs);
+                let code = *code;
+                // The output keeps being read while the others stop
+                thread::spawn(move || signal::shutdown(procs, code, opts));
+            }
+        }
+    }
+}
+
+// Records the time if the trigger fires
+fn is_due(
+    fired: &mut Vec<(String, usize, Instant)>,
+    name: &str,
+    index: usize,
+    now: Instant,
+    cooldown: Duration,
+) -> bool {
+    match fired.iter_mut().find(|(n, i, _)| n == name && *i == index) {
+        Some((_, _, at)) if now.duration_since(*at) < cooldown => false,
+        Some((_, _, at)) => {
+            *at = now;
+            true
+        }
+        None => {
+            fired.push((name.to_string(), index, now));
+            true
+        }
+    }
+}
+
+// e.g.) api/worker for worker when api/web matched, as names in config files are those of the Procfile
+fn target_type(process_type: &str, process: &str) -> String {
+    match process_type.rsplit_once('/') {
+        Some((app, _)) => format!("{}/{}", app, process),
+        None => process.to_string(),
+    }
+}
+
+// In the working directory of the process, with its output among the lines of the process
+fn run(
+    command: &str,
+    line: &str,
+    proc: &Arc<Mutex<Process>>,
+    opts: &DisplayOpts,
+) -> std::io::Result<()> {
+    let (name, index, mut env, config) = {
+        let proc = proc.lock().unwrap();
+        (
+            proc.name.clone(),
+            proc.index,
+            proc.env.clone(),
+            proc.config.clone(),
+        )
+    };
+    env.insert(String::from("MATCHED_LINE"), line.to_string());
+
+    // stdout and stderr share a pipe, so that their lines stay in order
+    let (read, write) = pipe2(OFlag::O_CLOEXEC).map_err(|_| std::io::Error::last_os_error())?;
+    let (read, write) = unsafe { (File::from_raw_fd(read), File::from_raw_fd(write)) };
+    let pid = {
+        let mut command = shell::command(command, config.shell.as_deref(), false);
+        if let Some(cwd) = &config.cwd {
+            command.current_dir(cwd);
+        }
+        command
+            .envs(&env)
+            .stdin(Stdio::null())
+            .stdout(write.try_clone()?)
+            .stderr(write)
+            .spawn()?
+            .id()
+    };
+    // Its status goes to the thread that reaps every child, and is taken from there
+    thread::spawn(move || process::wait_unclaimed(pid, None));
+
+    let log_opt = LogOpt {
+        is_color: true,
+        padding: opts.padding,
+        is_timestamp: opts.is_timestamp,
+    };
+    thread::spawn(move || {
+        for line in BufReader::new(read).lines().map_while(Result::ok) {
+            log::output(&name, &line, Some(index), &log_opt);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_is_due() -> anyhow::Result<()> {
+        let mut fired = vec![];
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(30);
+        assert!(is_due(&mut fired, "web.1", 0, now, cooldown));
+        assert!(!is_due(
+            &mut fired,
+            "web.1",
+            0,
+            now + Duration::from_secs(10),
+            cooldown
+        ));
+        // Another trigger, and another instance
+        assert!(is_due(&mut fired, "web.1", 1, now, cooldown));
+        assert!(is_due(&mut fired, "web.2", 0, now, cooldown));
+        assert!(is_due(
+            &mut fired,
+            "web.1",
+            0,
+            now + Duration::from_secs(30),
+            cooldown
+        ));
+        assert!(!is_due(
+            &mut fired,
+            "web.1",
+            0,
+            now + Duration::from_secs(40),
+            cooldown
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_type() -> anyhow::Result<()> {
+        assert_eq!(target_type("web", "worker"), "worker");
+        assert_eq!(target_type("api/web", "worker"), "api/worker");
+
+        Ok(())
+    }
+}
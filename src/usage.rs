@@ -0,0 +1,301 @@
+use serde_derive::Deserialize;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct UsageConfig {
+    /// MiB of memory (RSS) for the whole process tree
+    pub max_rss: Option<u64>,
+    /// Percent of one CPU for the whole process tree, so it can be over 100
+    pub max_cpu: Option<f64>,
+    /// Seconds over a threshold before the action is taken
+    #[serde(default = "default_sustain")]
+    pub sustain: u64,
+    #[serde(default)]
+    pub action: UsageAction,
+}
+
+fn default_sustain() -> u64 {
+    10
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageAction {
+    #[default]
+    Warn,
+    Restart,
+    Kill,
+}
+
+// What a process uses, summed over every process in its session
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    // Clock ticks of CPU time, including children that have been waited for
+    pub ticks: u64,
+    // Pages
+    pub rss: u64,
+}
+
+// e.g.) 1234 (sh) S 1000 1234 1234 ...
+// Returns the session and the usage of the process
+pub fn parse_stat(stat: &str) -> Option<(i32, Usage)> {
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+    let fields = after_comm.split_whitespace().collect::<Vec<_>>();
+    let field = |n: usize| fields.get(n)?.parse::<u64>().ok();
+
+    let session = fields.get(3)?.parse::<i32>().ok()?;
+    // utime, stime, cutime and cstime
+    let ticks = field(11)? + field(12)? + field(13)? + field(14)?;
+    Some((
+        session,
+        Usage {
+            ticks,
+            rss: field(21)?,
+        },
+    ))
+}
+
+// e.g.) 812.0MB
+pub fn format_bytes(bytes: u64) -> String {
+    format!("{:.1}MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+// Reading /proc, so Linux only
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{format_bytes, parse_stat, Usage, UsageAction};
+    use crate::log;
+    use crate::opt::DisplayOpts;
+    use crate::process::{self, Process, ProcessState};
+    use crate::signal;
+
+    use nix::unistd::{sysconf, SysconfVar};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, sleep, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+    struct Sample {
+        ticks: u64,
+        at: Instant,
+        cpu: Option<f64>,
+        rss: u64,
+        over_since: Option<Instant>,
+        is_handled: bool,
+    }
+
+    // Sample the usage of every process tree, take the action of a process that stays over its
+    // thresholds, and print a summary every summary_interval
+    pub fn build_usage_thread(
+        procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        summary_interval: Option<Duration>,
+        opts: DisplayOpts,
+    ) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(String::from("sample usage"))
+            .spawn(move || {
+                let page_size = sysconf(SysconfVar::PAGE_SIZE)
+                    .ok()
+                    .flatten()
+                    .unwrap_or(4096) as u64;
+                let clock_ticks = sysconf(SysconfVar::CLK_TCK).ok().flatten().unwrap_or(100) as f64;
+
+                // By child pid, so that a restarted process starts over
+                let mut samples: HashMap<u32, Sample> = HashMap::new();
+                let mut summarized_at = Instant::now();
+
+                loop {
+                    sleep(SAMPLE_INTERVAL);
+                    if signal::is_shutting_down() {
+                        return;
+                    }
+                    let sessions = read_sessions();
+                    let now = Instant::now();
+
+                    let running = procs
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|p| p.lock().unwrap().state == ProcessState::Running)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    samples.retain(|pid, _| {
+                        running.iter().any(|p| p.lock().unwrap().child.id() == *pid)
+                    });
+
+                    for proc in running.iter() {
+                        let (pid, name, config) = {
+                            let proc = proc.lock().unwrap();
+                            (
+                                proc.child.id(),
+                                proc.name.clone(),
+                                proc.config.usage.clone(),
+                            )
+                        };
+                        let usage = sessions.get(&(pid as i32)).copied().unwrap_or_default();
+                        let rss = usage.rss * page_size;
+
+                        let sample = samples.entry(pid).or_insert(Sample {
+                            ticks: usage.ticks,
+                            at: now,
+                            cpu: None,
+                            rss,
+                            over_since: None,
+                            is_handled: false,
+                        });
+                        if sample.at != now {
+                            let elapsed = now.duration_since(sample.at).as_secs_f64();
+                            let ticks = usage.ticks.saturating_sub(sample.ticks) as f64;
+                            sample.cpu = Some(ticks / clock_ticks / elapsed * 100.0);
+                        }
+                        sample.ticks = usage.ticks;
+                        sample.at = now;
+                        sample.rss = rss;
+
+                        let config = match config {
+                            Some(c) => c,
+                            None => continue,
+                        };
+                        let mut over = vec![];
+                        if let Some(max_rss) = config.max_rss {
+                            if rss > max_rss.saturating_mul(1024 * 1024) {
+                                over.push(format!(
+                                    "memory {} over {}MB",
+                                    format_bytes(rss),
+                                    max_rss
+                                ));
+                            }
+                        }
+                        if let (Some(max_cpu), Some(cpu)) = (config.max_cpu, sample.cpu) {
+                            if cpu > max_cpu {
+                                over.push(format!("CPU {:.1}% over {}%", cpu, max_cpu));
+                            }
+                        }
+                        if over.is_empty() {
+                            sample.over_since = None;
+                            sample.is_handled = false;
+                            continue;
+                        }
+
+                        let over_since = *sample.over_since.get_or_insert(now);
+                        if sample.is_handled
+                            || now.duration_since(over_since) < Duration::from_secs(config.sustain)
+                        {
+                            continue;
+                        }
+                        sample.is_handled = true;
+
+                        let reason = format!(
+                            "{0:1$} {2} for {3}s",
+                            name,
+                            opts.padding,
+                            over.join(", "),
+                            config.sustain
+                        );
+                        match config.action {
+                            UsageAction::Warn => log::system(&opts, &reason),
+                            UsageAction::Restart => {
+                                log::system(&opts, &format!("{}, restarting", reason));
+                                let proc = Arc::clone(proc);
+                                let opts = opts.clone();
+                                thread::spawn(move || process::restart(&proc, &opts));
+                            }
+                            UsageAction::Kill => {
+                                log::system(&opts, &format!("{}, killing", reason));
+                                proc.lock().unwrap().kill(&opts);
+                            }
+                        }
+                    }
+
+                    if let Some(interval) = summary_interval {
+                        if now.duration_since(summarized_at) >= interval {
+                            summarized_at = now;
+                            log::system(&opts, &summary(&running, &samples));
+                        }
+                    }
+                }
+            })
+            .expect("failed sample usage")
+    }
+
+    // e.g.) usage: web.1 120.5MB 2.0%, worker.1 812.0MB 98.5%
+    fn summary(running: &[Arc<Mutex<Process>>], samples: &HashMap<u32, Sample>) -> String {
+        let usages = running
+            .iter()
+            .filter_map(|p| {
+                let proc = p.lock().unwrap();
+                let sample = samples.get(&proc.child.id())?;
+                Some(format!(
+                    "{} {} {}",
+                    proc.name,
+                    format_bytes(sample.rss),
+                    sample
+                        .cpu
+                        .map(|c| format!("{:.1}%", c))
+                        .unwrap_or_else(|| String::from("-"))
+                ))
+            })
+            .collect::<Vec<_>>();
+        format!("usage: {}", usages.join(", "))
+    }
+
+    // Usage by session, as each process runs in its own session together with its descendants
+    fn read_sessions() -> HashMap<i32, Usage> {
+        let mut sessions: HashMap<i32, Usage> = HashMap::new();
+        let entries = match fs::read_dir("/proc") {
+            Ok(e) => e,
+            Err(_) => return sessions,
+        };
+
+        for pid in entries.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        {
+            let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            if let Some((session, usage)) = parse_stat(&stat) {
+                let total = sessions.entry(session).or_default();
+                total.ticks += usage.ticks;
+                total.rss += usage.rss;
+            }
+        }
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow;
+
+    #[test]
+    fn test_parse_stat() -> anyhow::Result<()> {
+        let stat = "1234 (npm run (dev)) S 1000 1200 1100 0 -1 4194560 500 0 0 0 150 50 10 5 20 0 1 0 100 10000000 2048 18446744073709551615";
+        assert_eq!(
+            parse_stat(stat),
+            Some((
+                1100,
+                Usage {
+                    ticks: 215,
+                    rss: 2048
+                }
+            ))
+        );
+        assert_eq!(parse_stat("1234 (sh) S 1000"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_bytes() -> anyhow::Result<()> {
+        assert_eq!(format_bytes(0), "0.0MB");
+        assert_eq!(format_bytes(812 * 1024 * 1024 + 512 * 1024), "812.5MB");
+
+        Ok(())
+    }
+}
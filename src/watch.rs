@@ -0,0 +1,384 @@
+use regex::Regex;
+use serde_derive::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct WatchConfig {
+    /// Globs relative to the working directory, e.g. "src/**/*.rb"
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Milliseconds to wait for more changes before restarting
+    #[serde(default = "default_debounce")]
+    pub debounce: u64,
+}
+
+fn default_debounce() -> u64 {
+    300
+}
+
+pub struct Matcher {
+    paths: Vec<Regex>,
+    ignore: Vec<Regex>,
+}
+
+impl Matcher {
+    pub fn new(config: &WatchConfig) -> Self {
+        Matcher {
+            paths: config.paths.iter().map(|g| glob_to_regex(g)).collect(),
+            ignore: config.ignore.iter().map(|g| glob_to_regex(g)).collect(),
+        }
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.paths.iter().any(|re| re.is_match(path)) && !self.is_ignored(path)
+    }
+
+    // A directory is ignored when everything below it is, e.g. "target/" for "target/**"
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.ignore.iter().any(|re| re.is_match(path))
+    }
+}
+
+// e.g.) src/**/*.rb => ^src/(?:.*/)?[^/]*\.rb$
+pub fn glob_to_regex(glob: &str) -> Regex {
+    let glob = glob.trim_start_matches("./");
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).unwrap_or_else(|_| panic!("Invalid watch glob: {}", glob))
+}
+
+// The directory to watch for a glob, e.g. "src" for "src/**/*.rb" and "." for "*.rb"
+pub fn base_dir(glob: &str) -> PathBuf {
+    let mut base = PathBuf::from(".");
+    let components = Path::new(glob).components().collect::<Vec<_>>();
+    for (i, component) in components.iter().enumerate() {
+        let part = component.as_os_str().to_string_lossy();
+        // The last component is the file name itself
+        if part.contains(['*', '?']) || i == components.len() - 1 {
+            break;
+        }
+        match component {
+            Component::CurDir => (),
+            _ => base.push(component),
+        }
+    }
+    base
+}
+
+// e.g.) ./src/app.rb => src/app.rb
+pub fn relative(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    path.trim_start_matches("./").to_string()
+}
+
+// inotify is Linux only
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{base_dir, relative, Matcher, WatchConfig};
+    use crate::log;
+    use crate::opt::DisplayOpts;
+    use crate::process::{self, Process};
+
+    use nix::errno::Errno;
+    use nix::poll::{poll, PollFd, PollFlags};
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    struct Watch {
+        name: String,
+        matcher: Matcher,
+        debounce: Duration,
+    }
+
+    // The files that changed in order, and when to act on them
+    struct Pending {
+        files: Vec<String>,
+        deadline: Instant,
+    }
+
+    pub fn build_watch_thread(
+        procs: Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        configs: Vec<(String, WatchConfig)>,
+        opts: DisplayOpts,
+    ) -> JoinHandle<()> {
+        let watches = configs
+            .iter()
+            .map(|(name, config)| Watch {
+                name: name.clone(),
+                matcher: Matcher::new(config),
+                debounce: Duration::from_millis(config.debounce),
+            })
+            .collect::<Vec<_>>();
+        let roots = configs
+            .iter()
+            .flat_map(|(_, config)| config.paths.iter().map(|glob| (base_dir(glob), true)))
+            .collect::<Vec<_>>();
+
+        spawn_watcher("watch files", watches, roots, move |name, file| {
+            restart_type(&procs, name, file, &opts)
+        })
+    }
+
+    // Watch single files, e.g. the Procfile. Their directories are watched instead of the files
+    // themselves, because editors often replace a file rather than write to it.
+    pub fn build_file_watch_thread<F>(files: Vec<PathBuf>, on_change: F) -> JoinHandle<()>
+    where
+        F: Fn(&str) + Send + 'static,
+    {
+        let globs = files
+            .iter()
+            .map(|f| relative(&Path::new(".").join(f)))
+            .collect::<Vec<_>>();
+        let roots = globs
+            .iter()
+            .map(|glob| (base_dir(glob), false))
+            .collect::<Vec<_>>();
+        let watches = vec![Watch {
+            name: String::new(),
+            matcher: Matcher::new(&WatchConfig {
+                paths: globs,
+                ignore: vec![],
+                debounce: 0,
+            }),
+            debounce: Duration::from_millis(300),
+        }];
+
+        spawn_watcher("watch loaded files", watches, roots, move |_, file| {
+            on_change(file)
+        })
+    }
+
+    // Calls on_change with the name of the watch and the files that changed, once they have
+    // stopped changing for the watch's debounce
+    fn spawn_watcher<F>(
+        thread_name: &str,
+        watches: Vec<Watch>,
+        roots: Vec<(PathBuf, bool)>,
+        on_change: F,
+    ) -> JoinHandle<()>
+    where
+        F: Fn(&str, &str) + Send + 'static,
+    {
+        thread::Builder::new()
+            .name(String::from(thread_name))
+            .spawn(move || {
+                let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+                    .expect("failed init inotify");
+                let mut dirs = HashMap::new();
+                for (dir, is_recursive) in roots.iter() {
+                    add_watch(inotify, dir, *is_recursive, &watches, &mut dirs);
+                }
+
+                let mut pending: HashMap<String, Pending> = HashMap::new();
+                loop {
+                    // Sleep until something changes or the earliest debounce is over
+                    let timeout = pending
+                        .values()
+                        .map(|p| p.deadline.saturating_duration_since(Instant::now()))
+                        .min()
+                        .map_or(-1, |d| d.as_millis() as i32);
+                    let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+                    match poll(&mut fds, timeout) {
+                        Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => (),
+                        Err(e) => panic!("failed watch files: {}", e),
+                    }
+
+                    for path in read_changes(inotify, &watches, &mut dirs) {
+                        for watch in watches.iter().filter(|w| w.matcher.is_match(&path)) {
+                            let deadline = Instant::now() + watch.debounce;
+                            let changed = pending.entry(watch.name.clone()).or_insert(Pending {
+                                files: vec![],
+                                deadline,
+                            });
+                            changed.deadline = deadline;
+                            if !changed.files.contains(&path) {
+                                changed.files.push(path.clone());
+                            }
+                        }
+                    }
+
+                    let now = Instant::now();
+                    let due = pending
+                        .iter()
+                        .filter(|(_, p)| p.deadline <= now)
+                        .map(|(name, _)| name.clone())
+                        .collect::<Vec<_>>();
+                    for name in due {
+                        let changed = pending.remove(&name).unwrap();
+                        let file = match changed.files.len() {
+                            1 => changed.files[0].clone(),
+                            n => format!("{} and {} more", changed.files[0], n - 1),
+                        };
+                        on_change(&name, &file);
+                    }
+                }
+            })
+            .expect("failed watch files")
+    }
+
+    fn add_watch(
+        inotify: Inotify,
+        dir: &Path,
+        is_recursive: bool,
+        watches: &[Watch],
+        dirs: &mut HashMap<WatchDescriptor, (PathBuf, bool)>,
+    ) {
+        let rel = format!("{}/", relative(dir));
+        if rel != "./" && watches.iter().all(|w| w.matcher.is_ignored(&rel)) {
+            return;
+        }
+
+        let mask = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO;
+        match inotify.add_watch(dir, mask) {
+            Ok(wd) => {
+                dirs.insert(wd, (dir.to_path_buf(), is_recursive));
+            }
+            // e.g.) the directory does not exist (yet)
+            Err(_) => return,
+        }
+        if !is_recursive {
+            return;
+        }
+
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    add_watch(inotify, &entry.path(), true, watches, dirs);
+                }
+            }
+        }
+    }
+
+    // Paths that changed, relative to the working directory. New directories are watched as well.
+    fn read_changes(
+        inotify: Inotify,
+        watches: &[Watch],
+        dirs: &mut HashMap<WatchDescriptor, (PathBuf, bool)>,
+    ) -> Vec<String> {
+        let mut changes = vec![];
+        while let Ok(events) = inotify.read_events() {
+            for event in events {
+                if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                    dirs.remove(&event.wd);
+                    continue;
+                }
+                let (path, is_recursive) = match (dirs.get(&event.wd), &event.name) {
+                    (Some((dir, is_recursive)), Some(name)) => (dir.join(name), *is_recursive),
+                    _ => continue,
+                };
+                if event.mask.contains(AddWatchFlags::IN_ISDIR) {
+                    if is_recursive
+                        && event
+                            .mask
+                            .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+                    {
+                        add_watch(inotify, &path, true, watches, dirs);
+                    }
+                    continue;
+                }
+                changes.push(relative(&path));
+            }
+        }
+        changes
+    }
+
+    fn restart_type(
+        procs: &Arc<Mutex<Vec<Arc<Mutex<Process>>>>>,
+        name: &str,
+        file: &str,
+        opts: &DisplayOpts,
+    ) {
+        log::system(opts, &format!("{} changed, restarting {}", file, name));
+
+        let instances = procs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| p.lock().unwrap().process_type() == name)
+            .cloned()
+            .collect::<Vec<_>>();
+        for proc in instances {
+            let opts = opts.clone();
+            thread::spawn(move || process::restart(&proc, &opts));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_to_regex() -> anyhow::Result<()> {
+        let re = glob_to_regex("src/**/*.rb");
+        assert!(re.is_match("src/app.rb"));
+        assert!(re.is_match("src/models/user.rb"));
+        assert!(!re.is_match("src/app.rbx"));
+        assert!(!re.is_match("lib/app.rb"));
+
+        let re = glob_to_regex("./*.toml");
+        assert!(re.is_match("Cargo.toml"));
+        assert!(!re.is_match("sub/Cargo.toml"));
+
+        let re = glob_to_regex("target/**");
+        assert!(re.is_match("target/"));
+        assert!(re.is_match("target/debug/app"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_matcher() -> anyhow::Result<()> {
+        let matcher = Matcher::new(&WatchConfig {
+            paths: vec![String::from("src/**/*.rs")],
+            ignore: vec![String::from("**/generated/**")],
+            debounce: 300,
+        });
+        assert!(matcher.is_match("src/main.rs"));
+        assert!(!matcher.is_match("src/generated/schema.rs"));
+        assert!(matcher.is_ignored("src/generated/"));
+        assert!(!matcher.is_ignored("src/"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_dir() -> anyhow::Result<()> {
+        assert_eq!(base_dir("src/**/*.rb"), PathBuf::from("./src"));
+        assert_eq!(base_dir("*.rb"), PathBuf::from("."));
+        assert_eq!(base_dir("./config/app.yml"), PathBuf::from("./config"));
+
+        Ok(())
+    }
+}